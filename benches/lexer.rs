@@ -0,0 +1,94 @@
+//! Throughput of the lexer on identifier heavy input.
+//!
+//! Run with `cargo bench`. Uses only std, every case runs a few times and the
+//! best run is reported.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use lang::lexer::get_token;
+use lang::{File, Lexer};
+
+const RUNS: usize = 5;
+
+// Words mixing keywords, near misses of keywords and plain names.
+const WORDS: &[&str] = &[
+    "function", "value", "if", "index", "return", "result", "while", "counter", "for", "format",
+    "and", "andrew", "let", "letter", "continue", "continuation", "x", "some_long_variable_name",
+    "import", "imports", "match", "matcher", "nil", "nothing", "সংখ্যা", "true", "truth",
+];
+
+// About `lines` lines of code that is mostly identifiers and keywords.
+fn identifier_source(lines: usize) -> Vec<u8> {
+    let mut source = String::new();
+    for i in 0..lines {
+        for j in 0..8 {
+            source.push_str(WORDS[(i * 7 + j * 3) % WORDS.len()]);
+            source.push(' ');
+        }
+        source.push('\n');
+    }
+    source.into_bytes()
+}
+
+// Best time of `RUNS` runs of `f`.
+fn best(mut f: impl FnMut()) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn report(name: &str, bytes: usize, tokens: usize, elapsed: Duration) {
+    let seconds = elapsed.as_secs_f64();
+    println!(
+        "{:<11} {:>8.2} MB/s {:>10.0} tokens/s ({} bytes, {} tokens, {:?})",
+        name,
+        bytes as f64 / seconds / 1e6,
+        tokens as f64 / seconds,
+        bytes,
+        tokens,
+        elapsed
+    );
+}
+
+fn main() {
+    let source = identifier_source(100_000);
+    let bytes = source.len();
+
+    // the lexer on its own, streaming tokens without keeping them.
+    let mut tokens = 0;
+    let elapsed = best(|| {
+        tokens = black_box(Lexer::new(black_box(&source)).count());
+    });
+    report("lexer", bytes, tokens, elapsed);
+
+    // tokenize also collects the tokens into the File. the Files are made
+    // before timing and dropped after it, so copying the source and freeing
+    // the tokens isn't part of it.
+    let mut files: Vec<File> = (0..RUNS).map(|_| File::new(source.clone())).collect();
+    let mut done = Vec::with_capacity(RUNS);
+    let elapsed = best(|| {
+        let mut file = files.pop().unwrap();
+        tokens = black_box(file.tokenize().unwrap()).len();
+        done.push(file);
+    });
+    report("tokenize", bytes, tokens, elapsed);
+
+    let lookups = 10_000_000;
+    let elapsed = best(|| {
+        for i in 0..lookups {
+            black_box(get_token(black_box(WORDS[i % WORDS.len()].as_bytes())));
+        }
+    });
+    println!(
+        "get_token   {:>8.2} ns/lookup ({} lookups, {:?})",
+        elapsed.as_nanos() as f64 / lookups as f64,
+        lookups,
+        elapsed
+    );
+}
@@ -0,0 +1,308 @@
+//! Syntax tree built by the parser.
+//!
+//! Every node carries the `Span` of the source it was parsed from. `Display`
+//! prints nodes as s-expressions like `(+ a (* b 2))`, which is what
+//! `lang parse` shows and what the parser tests compare against.
+
+use std::fmt;
+
+use crate::span::Span;
+
+/// A whole source file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub statements: Vec<Stmt>,
+    pub span: Span,
+}
+
+/// The statements of an `if`, `while`, `for` or `function` body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub statements: Vec<Stmt>,
+    pub span: Span,
+}
+
+/// A name, NFC normalized like the identifier token it comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StmtKind {
+    // an expression on its own, calls and assignments mostly.
+    Expr(Expr),
+    // `else if` is an else block holding just the inner if.
+    If {
+        condition: Expr,
+        then_block: Block,
+        else_block: Option<Block>,
+    },
+    While {
+        condition: Expr,
+        body: Block,
+    },
+    For {
+        variable: Ident,
+        iterable: Expr,
+        body: Block,
+    },
+    Function {
+        name: Ident,
+        params: Vec<Ident>,
+        body: Block,
+    },
+    Return(Option<Expr>),
+    // a statement that didn't parse.
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    Identifier(String),
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Bool(bool),
+    Nil,
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    // `target` is an identifier, index or member expression.
+    Assign {
+        op: AssignOp,
+        target: Box<Expr>,
+        value: Box<Expr>,
+    },
+    Call {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    Index {
+        object: Box<Expr>,
+        index: Box<Expr>,
+    },
+    Member {
+        object: Box<Expr>,
+        name: Ident,
+    },
+    // an expression that didn't parse, or input the lexer couldn't read.
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,    // -
+    Not,    // ! and not
+    BitNot, // ~
+    Try,    // postfix ?
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,  // || and or
+    And, // && and and
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    IntDiv, // //
+    Pow,    // **
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    Range,          // ..
+    RangeInclusive, // ..=
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssignOp {
+    Assign, // =
+    Add,    // +=
+    Sub,    // -=
+    Mul,    // *=
+    Div,    // /=
+    Rem,    // %=
+    Pow,    // **=
+    IntDiv, // //=
+}
+
+impl UnaryOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "-",
+            UnaryOp::Not => "not",
+            UnaryOp::BitNot => "~",
+            UnaryOp::Try => "?",
+        }
+    }
+}
+
+impl BinaryOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Or => "or",
+            BinaryOp::And => "and",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::IntDiv => "//",
+            BinaryOp::Pow => "**",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "^",
+            BinaryOp::ShiftLeft => "<<",
+            BinaryOp::ShiftRight => ">>",
+            BinaryOp::Range => "..",
+            BinaryOp::RangeInclusive => "..=",
+        }
+    }
+}
+
+impl AssignOp {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            AssignOp::Assign => "=",
+            AssignOp::Add => "+=",
+            AssignOp::Sub => "-=",
+            AssignOp::Mul => "*=",
+            AssignOp::Div => "/=",
+            AssignOp::Rem => "%=",
+            AssignOp::Pow => "**=",
+            AssignOp::IntDiv => "//=",
+        }
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, statement) in self.statements.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", statement)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(block")?;
+        for statement in &self.statements {
+            write!(f, " {}", statement)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl fmt::Display for Ident {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            StmtKind::Expr(expr) => write!(f, "{}", expr),
+            StmtKind::If {
+                condition,
+                then_block,
+                else_block,
+            } => {
+                write!(f, "(if {} {}", condition, then_block)?;
+                if let Some(block) = else_block {
+                    write!(f, " {}", block)?;
+                }
+                write!(f, ")")
+            }
+            StmtKind::While { condition, body } => write!(f, "(while {} {})", condition, body),
+            StmtKind::For {
+                variable,
+                iterable,
+                body,
+            } => write!(f, "(for {} {} {})", variable, iterable, body),
+            StmtKind::Function { name, params, body } => {
+                write!(f, "(function {} (", name)?;
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", param)?;
+                }
+                write!(f, ") {})", body)
+            }
+            StmtKind::Return(None) => write!(f, "(return)"),
+            StmtKind::Return(Some(value)) => write!(f, "(return {})", value),
+            StmtKind::Error => write!(f, "(error)"),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ExprKind::Identifier(name) => write!(f, "{}", name),
+            ExprKind::Integer(value) => write!(f, "{}", value),
+            // Debug keeps the dot in whole floats like 1.0
+            ExprKind::Float(value) => write!(f, "{:?}", value),
+            ExprKind::String(value) => write!(f, "{:?}", value),
+            ExprKind::Bool(value) => write!(f, "{}", value),
+            ExprKind::Nil => write!(f, "nil"),
+            ExprKind::Unary { op, operand } => write!(f, "({} {})", op.symbol(), operand),
+            ExprKind::Binary { op, left, right } => {
+                write!(f, "({} {} {})", op.symbol(), left, right)
+            }
+            ExprKind::Assign { op, target, value } => {
+                write!(f, "({} {} {})", op.symbol(), target, value)
+            }
+            ExprKind::Call { callee, args } => {
+                write!(f, "(call {}", callee)?;
+                for arg in args {
+                    write!(f, " {}", arg)?;
+                }
+                write!(f, ")")
+            }
+            ExprKind::Index { object, index } => write!(f, "(index {} {})", object, index),
+            ExprKind::Member { object, name } => write!(f, "(. {} {})", object, name),
+            ExprKind::Error => write!(f, "(error)"),
+        }
+    }
+}
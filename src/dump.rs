@@ -0,0 +1,169 @@
+//! Token dumps for tooling and golden tests.
+//!
+//! Every format describes each token with the same fields:
+//!
+//! - `kind`: the token kind, see `TokenType::name`. Names are stable.
+//! - `lexeme`: the source text of the token. Layout tokens (Newline at the end
+//!   of the file, Indent, Dedent, Eof) can be empty.
+//! - `start`, `end`: half-open byte range of the token in the source.
+//! - `line`, `column`: 1 based position of `start`, columns count characters.
+//!
+//! `json` prints an array with one object per token and per line:
+//!
+//! ```text
+//! [
+//!   {"kind": "Identifier", "lexeme": "a", "span": {"start": 0, "end": 1}, "line": 1, "column": 1},
+//!   ...
+//! ]
+//! ```
+//!
+//! `table` prints one aligned row per token, `debug` is the Rust `Debug` output
+//! and is not meant to be parsed.
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::lexer::File;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Table,
+    Debug,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Format::Json),
+            "table" => Ok(Format::Table),
+            "debug" => Ok(Format::Debug),
+            _ => Err(format!("unknown format '{}', expected json, table or debug", s)),
+        }
+    }
+}
+
+/// Dump the tokens from the last `tokenize` call on `file` in `format`.
+pub fn dump(file: &File, format: Format) -> String {
+    match format {
+        Format::Json => json(file),
+        Format::Table => table(file),
+        Format::Debug => file.dump_tokens(),
+    }
+}
+
+fn json(file: &File) -> String {
+    let mut out = String::from("[\n");
+    let tokens = file.tokens();
+    for (i, token) in tokens.iter().enumerate() {
+        let separator = if i + 1 < tokens.len() { "," } else { "" };
+        // writing to a String can't fail.
+        let _ = writeln!(
+            out,
+            "  {{\"kind\": \"{}\", \"lexeme\": {}, \"span\": {{\"start\": {}, \"end\": {}}}, \"line\": {}, \"column\": {}}}{}",
+            token.token_type.name(),
+            json_string(&token.text(file)),
+            token.span.start,
+            token.span.end,
+            token.span.line,
+            token.span.col,
+            separator,
+        );
+    }
+    out.push(']');
+    out
+}
+
+// `text` as a quoted json string.
+fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn table(file: &File) -> String {
+    let mut out = format!("{:<10} {:<12} {:<14} {}\n", "LINE:COL", "SPAN", "KIND", "LEXEME");
+    for token in file.tokens() {
+        let position = format!("{}:{}", token.span.line, token.span.col);
+        let span = format!("{}..{}", token.span.start, token.span.end);
+        // escape the lexeme so every token stays on one row.
+        let lexeme = token.text(file).escape_debug().to_string();
+        let _ = writeln!(
+            out,
+            "{:<10} {:<12} {:<14} {}",
+            position,
+            span,
+            token.token_type.name(),
+            lexeme
+        );
+    }
+    // like json, no newline after the last row.
+    out.pop();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokenized(source: &str) -> File {
+        let mut file = File::new(source.as_bytes().to_vec());
+        file.tokenize().unwrap();
+        file
+    }
+
+    #[test]
+    fn json_schema() {
+        let file = tokenized("a = \"x\\n\"");
+        assert_eq!(
+            dump(&file, Format::Json),
+            r#"[
+  {"kind": "Identifier", "lexeme": "a", "span": {"start": 0, "end": 1}, "line": 1, "column": 1},
+  {"kind": "Assign", "lexeme": "=", "span": {"start": 2, "end": 3}, "line": 1, "column": 3},
+  {"kind": "StringLiteral", "lexeme": "\"x\\n\"", "span": {"start": 4, "end": 9}, "line": 1, "column": 5},
+  {"kind": "Newline", "lexeme": "", "span": {"start": 9, "end": 9}, "line": 1, "column": 10},
+  {"kind": "Eof", "lexeme": "", "span": {"start": 9, "end": 9}, "line": 1, "column": 10}
+]"#
+        );
+    }
+
+    #[test]
+    fn json_escapes_control_characters() {
+        assert_eq!(json_string("a\"b\\c\u{1}"), r#""a\"b\\c\u0001""#);
+    }
+
+    #[test]
+    fn table_rows() {
+        let file = tokenized("x\n");
+        let table = dump(&file, Format::Table);
+        let rows: Vec<&str> = table.lines().collect();
+        assert_eq!(rows.len(), 4);
+        assert!(!table.ends_with('\n'));
+        assert!(rows[1].starts_with("1:1        0..1         Identifier     x"));
+        assert!(rows[2].ends_with("Newline        \\n"));
+    }
+
+    #[test]
+    fn format_names() {
+        assert_eq!("json".parse(), Ok(Format::Json));
+        assert_eq!("table".parse(), Ok(Format::Table));
+        assert_eq!("debug".parse(), Ok(Format::Debug));
+        assert!("xml".parse::<Format>().is_err());
+    }
+}
@@ -1,20 +1,41 @@
-use std::process::id;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fmt;
 
-#[derive(Debug, Clone)]
+use unicode_ident::{is_xid_continue, is_xid_start};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::span::{self, SourceMap, Span};
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
     // Special
     Eof, // End of File
+    Error, // bad input skipped over in recovery mode
+
+    // Layout
+    Newline, // end of a logical line
+    Indent,  // line is indented deeper than the previous one
+    Dedent,  // line goes back to an outer indentation level
+
+    // only emitted when the File keeps comments.
+    Comment, // # line, /* block */
 
     // Identifiers & Literals
-    Identifier(Vec<u8>),    // variable names, function names
-    Integer,       // 123
-    Float,         // 12.3
-    StringLiteral, // "hello"
+    Identifier(Vec<u8>),    // variable names, function names. NFC normalized utf-8
+    Integer(i64),  // 123, 0xFF, 0o17, 0b1010, 1_000
+    Float(f64),    // 12.3, .5, 1.5e-3
+    StringLiteral(String), // "hello", with escape sequences decoded
 
     // Assignment Operators
-    Assign,      // =
-    PlusAssign,  // +=
-    MinusAssign, // -=
+    Assign,               // =
+    PlusAssign,           // +=
+    MinusAssign,          // -=
+    AsteriskAssign,       // *=
+    SlashAssign,          // /=
+    PercentAssign,        // %=
+    DoubleAsteriskAssign, // **=
+    DoubleSlashAssign,    // //=
 
     // Comparison Operators
     Equal,        // ==
@@ -27,13 +48,24 @@ pub enum TokenType {
     // Mathematical Operators
     Plus,     // +
     Minus,    // -
-    Asterisk, // *
-    Slash,    // /
+    Asterisk,       // *
+    Slash,          // /
+    Percent,        // %
+    DoubleAsterisk, // ** (power)
+    DoubleSlash,    // // (integer division)
+
+    // Logical Operators, short-circuiting.
+    AndAnd, // && (also the `and` keyword)
+    OrOr,   // || (also the `or` keyword)
+    Bang,   // !  (also the `not` keyword)
 
-    // Logical Operators
-    And,  // &
-    Or,   // |
-    Bang, // !
+    // Bitwise Operators
+    BitAnd,     // &
+    BitOr,      // |
+    Caret,      // ^
+    Tilde,      // ~
+    ShiftLeft,  // <<
+    ShiftRight, // >>
 
     // Delimiters (The "Glue" of syntax)
     Comma,       // ,
@@ -46,6 +78,15 @@ pub enum TokenType {
     LeftSquare,  // [
     RightSquare, // ]
 
+    // Access, Ranges and Arrows
+    Dot,         // .
+    DotDot,      // .. (exclusive range)
+    DotDotEq,    // ..= (inclusive range)
+    Arrow,       // ->
+    FatArrow,    // =>
+    Question,    // ?
+    DoubleColon, // ::
+
     // Keywords
     True,
     False,
@@ -55,10 +96,111 @@ pub enum TokenType {
     For,
     While,
     Return,
+    And, // and, same meaning as &&
+    Or,  // or, same meaning as ||
+    Not, // not, same meaning as !
+    Let,
+    Const,
+    Import,
+    From,
+    As,
+    Class,
+    Break,
+    Continue,
+    Nil,
+    In,
+    Match,
+}
+
+impl TokenType {
+    /// Stable name of the token kind, without any payload. Used by token dumps
+    /// meant for tooling, so existing names must not change.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TokenType::Eof => "Eof",
+            TokenType::Error => "Error",
+            TokenType::Newline => "Newline",
+            TokenType::Indent => "Indent",
+            TokenType::Dedent => "Dedent",
+            TokenType::Comment => "Comment",
+            TokenType::Identifier(_) => "Identifier",
+            TokenType::Integer(_) => "Integer",
+            TokenType::Float(_) => "Float",
+            TokenType::StringLiteral(_) => "StringLiteral",
+            TokenType::Assign => "Assign",
+            TokenType::PlusAssign => "PlusAssign",
+            TokenType::MinusAssign => "MinusAssign",
+            TokenType::AsteriskAssign => "AsteriskAssign",
+            TokenType::SlashAssign => "SlashAssign",
+            TokenType::PercentAssign => "PercentAssign",
+            TokenType::DoubleAsteriskAssign => "DoubleAsteriskAssign",
+            TokenType::DoubleSlashAssign => "DoubleSlashAssign",
+            TokenType::Equal => "Equal",
+            TokenType::NotEqual => "NotEqual",
+            TokenType::Less => "Less",
+            TokenType::LessEqual => "LessEqual",
+            TokenType::Greater => "Greater",
+            TokenType::GreaterEqual => "GreaterEqual",
+            TokenType::Plus => "Plus",
+            TokenType::Minus => "Minus",
+            TokenType::Asterisk => "Asterisk",
+            TokenType::Slash => "Slash",
+            TokenType::Percent => "Percent",
+            TokenType::DoubleAsterisk => "DoubleAsterisk",
+            TokenType::DoubleSlash => "DoubleSlash",
+            TokenType::AndAnd => "AndAnd",
+            TokenType::OrOr => "OrOr",
+            TokenType::Bang => "Bang",
+            TokenType::BitAnd => "BitAnd",
+            TokenType::BitOr => "BitOr",
+            TokenType::Caret => "Caret",
+            TokenType::Tilde => "Tilde",
+            TokenType::ShiftLeft => "ShiftLeft",
+            TokenType::ShiftRight => "ShiftRight",
+            TokenType::Comma => "Comma",
+            TokenType::Semicolon => "Semicolon",
+            TokenType::Colon => "Colon",
+            TokenType::LeftParen => "LeftParen",
+            TokenType::RightParen => "RightParen",
+            TokenType::LeftBrace => "LeftBrace",
+            TokenType::RightBrace => "RightBrace",
+            TokenType::LeftSquare => "LeftSquare",
+            TokenType::RightSquare => "RightSquare",
+            TokenType::Dot => "Dot",
+            TokenType::DotDot => "DotDot",
+            TokenType::DotDotEq => "DotDotEq",
+            TokenType::Arrow => "Arrow",
+            TokenType::FatArrow => "FatArrow",
+            TokenType::Question => "Question",
+            TokenType::DoubleColon => "DoubleColon",
+            TokenType::True => "True",
+            TokenType::False => "False",
+            TokenType::Function => "Function",
+            TokenType::If => "If",
+            TokenType::Else => "Else",
+            TokenType::For => "For",
+            TokenType::While => "While",
+            TokenType::Return => "Return",
+            TokenType::And => "And",
+            TokenType::Or => "Or",
+            TokenType::Not => "Not",
+            TokenType::Let => "Let",
+            TokenType::Const => "Const",
+            TokenType::Import => "Import",
+            TokenType::From => "From",
+            TokenType::As => "As",
+            TokenType::Class => "Class",
+            TokenType::Break => "Break",
+            TokenType::Continue => "Continue",
+            TokenType::Nil => "Nil",
+            TokenType::In => "In",
+            TokenType::Match => "Match",
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
-pub enum Mode {
+pub(crate) enum Mode {
     Normal,
     StringLiteral,
     Integer,
@@ -67,557 +209,2231 @@ pub enum Mode {
     Identifier,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Token {
     pub token_type: TokenType,
-    pub start: usize,
-    pub end: usize,
-    pub line: usize,
+    pub span: Span,
+}
+
+impl Token {
+    /// The source text (lexeme) of this token in `file`. Layout tokens like
+    /// Indent and Dedent may be empty.
+    pub fn text<'a>(&self, file: &'a File) -> Cow<'a, str> {
+        String::from_utf8_lossy(&file.source()[self.span.start..self.span.end])
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexErrorKind {
+    UnknownCharacter(char),
+    InvalidUtf8,
+    InvalidInteger(char),
+    InvalidFloat(char),
+    TooManyDecimalPoints,
+    MissingDigits,
+    NumberOverflow,
+    UnterminatedString,
+    UnterminatedComment,
+    UnknownEscape(char),
+    InvalidHexEscape,
+    InvalidUnicodeEscape,
+    InconsistentDedent,
+    MixedIndentation,
+    ReservedKeyword(String),
+}
+
+/// A lexical error. `span` covers the offending input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub span: Span,
+}
+
+impl fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // characters are escaped, they can be control characters like a newline.
+            LexErrorKind::UnknownCharacter(c) => {
+                write!(f, "Unknown character '{}'", c.escape_debug())?
+            }
+            LexErrorKind::InvalidUtf8 => write!(f, "invalid utf-8")?,
+            LexErrorKind::InvalidInteger(c) => write!(f, "invalid integer '{}'", c.escape_debug())?,
+            LexErrorKind::InvalidFloat(c) => write!(f, "invalid Float '{}'", c.escape_debug())?,
+            LexErrorKind::TooManyDecimalPoints => write!(f, "too many floating points for integer")?,
+            LexErrorKind::MissingDigits => write!(f, "number literal is missing its digits")?,
+            LexErrorKind::NumberOverflow => write!(f, "number literal is too large")?,
+            LexErrorKind::UnterminatedString => write!(f, "StringLiteral did not close")?,
+            LexErrorKind::UnterminatedComment => write!(f, "block comment did not close")?,
+            LexErrorKind::UnknownEscape(c) => {
+                write!(f, "unknown escape sequence '\\{}'", c.escape_debug())?
+            }
+            LexErrorKind::InvalidHexEscape => {
+                write!(f, "invalid '\\x' escape, expected two hex digits up to 7F")?
+            }
+            LexErrorKind::InvalidUnicodeEscape => {
+                write!(f, "invalid '\\u' escape, expected '\\u{{...}}' with a valid code point")?
+            }
+            LexErrorKind::InconsistentDedent => {
+                write!(f, "dedent does not match any outer indentation level")?
+            }
+            LexErrorKind::MixedIndentation => write!(f, "indentation mixes tabs and spaces")?,
+            LexErrorKind::ReservedKeyword(word) => {
+                write!(f, "'{}' is a reserved keyword and can't be used as a name", word)?
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at line {}, column {}", self.kind, self.span.line, self.span.col)
+    }
+}
+
+impl std::error::Error for LexError {}
+
+// A word in the keyword table: a keyword, or a word kept back for future
+// versions of the language. Reserved words are not keywords yet but can't be
+// used as names either, so adding them later won't break code.
+enum Word {
+    Keyword(TokenType),
+    Reserved,
 }
 
-pub fn get_token(bytes: &[u8]) -> (Option<TokenType>, String) {
-    let value = unsafe { std::str::from_utf8_unchecked(bytes) }.to_string();
-    let token_type = match bytes {
-        // keywords.
-        b"function" => Some(TokenType::Function),
-        b"if" => Some(TokenType::If),
-        b"else" => Some(TokenType::Else),
-        b"for" => Some(TokenType::For),
-        b"while" => Some(TokenType::While),
-        b"return" => Some(TokenType::Return),
-        b"true" => Some(TokenType::True),
-        b"false" => Some(TokenType::False),
+// Keywords and reserved words bucketed by length. A lookup indexes the bucket
+// by the identifier's length and only compares against the few words in it,
+// identifiers longer than any keyword are not compared at all. Nothing allocates.
+// NOTE: keep every word in the bucket for its length, the tests check it.
+static KEYWORDS: [&[(&[u8], Word)]; 10] = [
+    &[],
+    &[],
+    &[
+        (b"if", Word::Keyword(TokenType::If)),
+        (b"or", Word::Keyword(TokenType::Or)),
+        (b"as", Word::Keyword(TokenType::As)),
+        (b"in", Word::Keyword(TokenType::In)),
+    ],
+    &[
+        (b"for", Word::Keyword(TokenType::For)),
+        (b"and", Word::Keyword(TokenType::And)),
+        (b"not", Word::Keyword(TokenType::Not)),
+        (b"let", Word::Keyword(TokenType::Let)),
+        (b"nil", Word::Keyword(TokenType::Nil)),
+        (b"pub", Word::Reserved),
+        (b"try", Word::Reserved),
+    ],
+    &[
+        (b"else", Word::Keyword(TokenType::Else)),
+        (b"true", Word::Keyword(TokenType::True)),
+        (b"from", Word::Keyword(TokenType::From)),
+        (b"enum", Word::Reserved),
+        (b"loop", Word::Reserved),
+        (b"self", Word::Reserved),
+        (b"type", Word::Reserved),
+    ],
+    &[
+        (b"while", Word::Keyword(TokenType::While)),
+        (b"false", Word::Keyword(TokenType::False)),
+        (b"const", Word::Keyword(TokenType::Const)),
+        (b"class", Word::Keyword(TokenType::Class)),
+        (b"break", Word::Keyword(TokenType::Break)),
+        (b"match", Word::Keyword(TokenType::Match)),
+        (b"async", Word::Reserved),
+        (b"await", Word::Reserved),
+        (b"catch", Word::Reserved),
+        (b"super", Word::Reserved),
+        (b"trait", Word::Reserved),
+        (b"yield", Word::Reserved),
+    ],
+    &[
+        (b"return", Word::Keyword(TokenType::Return)),
+        (b"import", Word::Keyword(TokenType::Import)),
+        (b"struct", Word::Reserved),
+    ],
+    &[],
+    &[
+        (b"function", Word::Keyword(TokenType::Function)),
+        (b"continue", Word::Keyword(TokenType::Continue)),
+    ],
+    &[(b"interface", Word::Reserved)],
+];
+
+fn lookup(bytes: &[u8]) -> Option<&'static Word> {
+    let bucket = KEYWORDS.get(bytes.len())?;
+    bucket.iter().find(|(word, _)| *word == bytes).map(|(_, kind)| kind)
+}
+
+/// The keyword token for `bytes`, None if it is not a keyword.
+pub fn get_token(bytes: &[u8]) -> Option<TokenType> {
+    match lookup(bytes) {
+        Some(Word::Keyword(token_type)) => Some(token_type.clone()),
         _ => None,
-    };
-    (token_type, value)
+    }
+}
+
+/// True for words reserved for future keywords, which can't be used as names.
+pub fn is_reserved(bytes: &[u8]) -> bool {
+    matches!(lookup(bytes), Some(Word::Reserved))
+}
+
+// True if `byte` can't continue a number literal, so the literal ends right
+// before it. letters, digits, `_` and `.` are part of the literal (or make it
+// invalid), anything else like whitespace, operators and delimiters ends it.
+fn ends_number(byte: u8) -> bool {
+    !(byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'.' || byte >= 0x80)
+}
+
+// True if `rest` of a line, starting with `/*`, is nothing but block comments
+// up to the end of the line (or a # comment). An unterminated comment counts
+// too, it is reported when it is lexed.
+fn only_block_comment(rest: &[u8]) -> bool {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < rest.len() {
+        if rest[i..].starts_with(b"/*") {
+            depth += 1;
+            i += 2;
+        } else if depth > 0 && rest[i..].starts_with(b"*/") {
+            depth -= 1;
+            i += 2;
+        } else if depth > 0 || matches!(rest[i], b' ' | b'\t') {
+            i += 1;
+        } else {
+            return matches!(rest[i], b'\n' | b'\r' | b'#');
+        }
+    }
+    true
+}
+
+// True if a line ending in `token_type` obviously goes on at the next line:
+// binary operators, assignments and commas need something after them. The
+// newline after them is not a Newline token and the next line's indentation
+// doesn't count. `?` and `)` can end an expression, `:` and `{` open blocks.
+fn continues_line(token_type: &TokenType) -> bool {
+    matches!(
+        token_type,
+        TokenType::Assign
+            | TokenType::PlusAssign
+            | TokenType::MinusAssign
+            | TokenType::AsteriskAssign
+            | TokenType::SlashAssign
+            | TokenType::PercentAssign
+            | TokenType::DoubleAsteriskAssign
+            | TokenType::DoubleSlashAssign
+            | TokenType::Equal
+            | TokenType::NotEqual
+            | TokenType::Less
+            | TokenType::LessEqual
+            | TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Plus
+            | TokenType::Minus
+            | TokenType::Asterisk
+            | TokenType::Slash
+            | TokenType::Percent
+            | TokenType::DoubleAsterisk
+            | TokenType::DoubleSlash
+            | TokenType::AndAnd
+            | TokenType::OrOr
+            | TokenType::Bang
+            | TokenType::BitAnd
+            | TokenType::BitOr
+            | TokenType::Caret
+            | TokenType::Tilde
+            | TokenType::ShiftLeft
+            | TokenType::ShiftRight
+            | TokenType::Comma
+            | TokenType::Dot
+            | TokenType::DotDot
+            | TokenType::DotDotEq
+            | TokenType::Arrow
+            | TokenType::FatArrow
+            | TokenType::DoubleColon
+            | TokenType::And
+            | TokenType::Or
+            | TokenType::Not
+            | TokenType::In
+    )
 }
 
-fn getchar_from_bytes(bytes: &[u8], position: usize) -> char {
+// Decodes the utf-8 character starting at `position`. Returns the character and
+// its length in bytes, None if the bytes there are not valid utf-8.
+fn decode_char(bytes: &[u8], position: usize) -> Option<(char, usize)> {
     // utf-8 can have a maximum of 4 bytes for multi byte character like 'π'
     let end_position = (position + 4).min(bytes.len());
-    let data = String::from_utf8_lossy(&bytes[position..end_position]);
-    data.chars().next().unwrap()
+    let chunk = &bytes[position..end_position];
+    let valid = match std::str::from_utf8(chunk) {
+        Ok(data) => data,
+        // the chunk may cut the next character in half, only the first one matters.
+        Err(e) => std::str::from_utf8(&chunk[..e.valid_up_to()]).unwrap(),
+    };
+    valid.chars().next().map(|c| (c, c.len_utf8()))
 }
 
+// Decodes the escape sequence at the start of `bytes` (which starts with the
+// backslash). Returns the decoded character or the error, together with the
+// length of the sequence in bytes so the caller can skip over it.
+fn decode_escape(bytes: &[u8]) -> (Result<char, LexErrorKind>, usize) {
+    let hex = |b: u8| (b as char).to_digit(16);
+    match bytes[1] {
+        b'n' => (Ok('\n'), 2),
+        b't' => (Ok('\t'), 2),
+        b'r' => (Ok('\r'), 2),
+        b'0' => (Ok('\0'), 2),
+        b'\\' => (Ok('\\'), 2),
+        b'"' => (Ok('"'), 2),
+        b'\'' => (Ok('\''), 2),
+
+        // \x.. takes exactly two hex digits, limited to ascii like rust does.
+        b'x' => {
+            let digits: Vec<u32> = bytes[2..].iter().take(2).map_while(|b| hex(*b)).collect();
+            if digits.len() < 2 {
+                return (Err(LexErrorKind::InvalidHexEscape), 2 + digits.len());
+            }
+            match digits[0] * 16 + digits[1] {
+                value @ 0..=0x7F => (Ok(char::from(value as u8)), 4),
+                _ => (Err(LexErrorKind::InvalidHexEscape), 4),
+            }
+        }
+
+        // \u{...} takes 1 to 6 hex digits that make up a unicode scalar value.
+        b'u' => {
+            if bytes.get(2) != Some(&b'{') {
+                return (Err(LexErrorKind::InvalidUnicodeEscape), 2);
+            }
+            let digits: Vec<u32> = bytes[3..].iter().take(7).map_while(|b| hex(*b)).collect();
+            let len = 3 + digits.len();
+            if bytes.get(len) != Some(&b'}') {
+                return (Err(LexErrorKind::InvalidUnicodeEscape), len);
+            }
+            let value = digits.iter().fold(0u32, |acc, d| acc.saturating_mul(16) + d);
+            match char::from_u32(value) {
+                Some(c) if !digits.is_empty() && digits.len() <= 6 => (Ok(c), len + 1),
+                _ => (Err(LexErrorKind::InvalidUnicodeEscape), len + 1),
+            }
+        }
+
+        _ => match decode_char(bytes, 1) {
+            Some((c, len)) => (Err(LexErrorKind::UnknownEscape(c)), 1 + len),
+            None => (Err(LexErrorKind::InvalidUtf8), 2),
+        },
+    }
+}
 
+/// Streaming lexer over a source buffer. Tokens are produced on demand as it
+/// is iterated, so the whole token stream never has to be in memory at once.
+/// The last token is always `TokenType::Eof`, unless lexing stopped at an
+/// error outside of recovery mode.
 #[derive(Debug)]
-pub struct File {
-    raw: Vec<u8>,
-    tokens: Vec<Token>,
+pub struct Lexer<'a> {
+    raw: &'a [u8],
     cursor: usize,
     line: usize,
+    // byte offset where the current line starts, used to compute columns.
+    line_start: usize,
+    // keep lexing after an error instead of stopping at the first one.
+    recover: bool,
+    errors: Vec<LexError>,
+    // tokens lexed but not yet handed out.
+    pending: VecDeque<Token>,
+    // set once Eof was queued or lexing stopped at an error.
+    finished: bool,
+
+    // Layout: the open indentation levels, innermost last, as the lowest and
+    // highest width that belongs to the level. they only differ after an
+    // InconsistentDedent. Always starts with (0, 0) for the top level.
+    indents: Vec<(usize, usize)>,
+    // the character used for indentation, set by the first indented line.
+    indent_char: Option<u8>,
+    // how many ( and [ are open. newlines and indentation inside them are ignored.
+    nesting: usize,
+    // for each open {, how many indentation levels were open at it. the } closes
+    // the levels opened since, so braced blocks don't depend on where } is indented.
+    braces: Vec<usize>,
+    // a token was emitted since the last Newline, so the logical line is open.
+    in_line: bool,
+    // the last token was an operator or comma, so the line goes on at the next one.
+    continues: bool,
+    at_line_start: bool,
+    // emit Comment tokens instead of dropping comments.
+    keep_comments: bool,
+
+    // State machine and the start of the token being lexed in each mode.
+    mode: Mode,
+    str_start: usize,
+    str_line: usize,
+    str_column: usize,
+    // decoded contents of the string being lexed.
+    str_value: Vec<u8>,
+    int_start: usize,
+    // radix of the integer being lexed, changed by the 0x, 0o and 0b prefixes.
+    radix: u32,
+    float_start: usize,
+    // whether the float being lexed already has an exponent.
+    exponent: bool,
+    id_start: usize,
+    comment_start: usize,
+    comment_line: usize,
+    comment_column: usize,
+    // 0 for line comments, otherwise how many /* are still open.
+    comment_depth: usize,
 }
 
-impl File {
-    pub fn new(raw_data: Vec<u8>) -> Self {
+impl<'a> Lexer<'a> {
+    pub fn new(raw: &'a [u8]) -> Self {
         Self {
-            raw: raw_data,
-            tokens: vec![],
+            raw,
             cursor: 0,
             line: 1,
+            line_start: 0,
+            recover: false,
+            errors: vec![],
+            pending: VecDeque::new(),
+            finished: false,
+            indents: vec![(0, 0)],
+            indent_char: None,
+            nesting: 0,
+            braces: vec![],
+            in_line: false,
+            continues: false,
+            at_line_start: true,
+            keep_comments: false,
+            mode: Mode::Normal,
+            str_start: 0,
+            str_line: 0,
+            str_column: 0,
+            str_value: vec![],
+            int_start: 0,
+            radix: 10,
+            float_start: 0,
+            exponent: false,
+            id_start: 0,
+            comment_start: 0,
+            comment_line: 0,
+            comment_column: 0,
+            comment_depth: 0,
+        }
+    }
+
+    /// Keep lexing after an error instead of stopping at the first one. Bad
+    /// input shows up as `TokenType::Error` tokens.
+    pub fn recovery(mut self, recover: bool) -> Self {
+        self.recover = recover;
+        self
+    }
+
+    /// Emit comments as `TokenType::Comment` tokens instead of dropping them.
+    pub fn keep_comments(mut self, keep: bool) -> Self {
+        self.keep_comments = keep;
+        self
+    }
+
+    /// Errors found so far.
+    pub fn errors(&self) -> &[LexError] {
+        &self.errors
+    }
+
+    /// The next token, without consuming it.
+    pub fn peek(&mut self) -> Option<&Token> {
+        self.peek_nth(0)
+    }
+
+    /// The token `n` positions ahead (0 is the next one), without consuming
+    /// anything. Only lexes as far as needed.
+    pub fn peek_nth(&mut self, n: usize) -> Option<&Token> {
+        self.fill(n + 1);
+        self.pending.get(n)
+    }
+
+    // Runs the state machine until `count` tokens are waiting or the file is done.
+    fn fill(&mut self, count: usize) {
+        while self.pending.len() < count && !self.finished {
+            if self.cursor >= self.raw.len() {
+                self.finish();
+            } else if !self.step() {
+                // stopped at an error outside of recovery mode.
+                self.finished = true;
+            }
+        }
+    }
+
+    // 1 based column of `offset` on the current line, counted in characters.
+    fn column(&self, offset: usize) -> usize {
+        span::column(&self.raw[self.line_start..offset])
+    }
+
+    // span of `start..end` where start is on the current line.
+    fn span(&self, start: usize, end: usize) -> Span {
+        Span::new(start, end.min(self.raw.len()), self.line, self.column(start))
+    }
+
+    // span of the string being lexed up to `end`, it may have started on an earlier line.
+    fn string_span(&self, end: usize) -> Span {
+        Span::new(self.str_start, end, self.str_line, self.str_column)
+    }
+
+    // span of the comment being lexed up to `end`, it may have started on an earlier line.
+    fn comment_span(&self, end: usize) -> Span {
+        Span::new(self.comment_start, end, self.comment_line, self.comment_column)
+    }
+
+    fn error(&self, kind: LexErrorKind, start: usize, end: usize) -> LexError {
+        LexError {
+            kind,
+            span: self.span(start, end),
+        }
+    }
+
+    // error for the single (possibly multi byte) character under the cursor.
+    fn char_error(&self, kind: fn(char) -> LexErrorKind) -> LexError {
+        match decode_char(self.raw, self.cursor) {
+            Some((c, len)) => self.error(kind(c), self.cursor, self.cursor + len),
+            None => self.error(LexErrorKind::InvalidUtf8, self.cursor, self.cursor + 1),
+        }
+    }
+
+    // Records `error`. Returns true if lexing should go on (recovery mode).
+    fn report(&mut self, error: LexError) -> bool {
+        self.errors.push(error);
+        self.recover
+    }
+
+    // Records `error` for a malformed number. In recovery mode the rest of the
+    // literal from `start` becomes an Error token and true is returned so lexing
+    // can go on. Otherwise returns false and the caller should stop.
+    fn recover(&mut self, error: LexError, start: usize) -> bool {
+        if !self.report(error) {
+            return false;
+        }
+
+        // sync on the first byte that can't be part of a number, so operators
+        // and delimiters right after the literal are still lexed normally.
+        while self.cursor < self.raw.len() && !ends_number(self.raw[self.cursor]) {
+            self.cursor += 1;
+        }
+        // the offending character itself is always part of the Error token.
+        self.cursor = self.cursor.max(start + 1).min(self.raw.len());
+
+        self.push(Token {
+            token_type: TokenType::Error,
+            span: self.span(start, self.cursor),
+        });
+        true
+    }
+
+    // byte `offset` positions after the cursor, None past the end of the file.
+    fn peek_byte(&self, offset: usize) -> Option<u8> {
+        self.raw.get(self.cursor + offset).copied()
+    }
+
+    // Consumes the e/E of a float exponent and its optional sign. The exponent
+    // needs at least one digit, `start` is where the float started.
+    fn lex_exponent(&mut self, start: usize) -> Result<(), LexError> {
+        self.cursor += 1;
+        if matches!(self.peek_byte(0), Some(b'+') | Some(b'-')) {
+            self.cursor += 1;
+        }
+        match self.peek_byte(0) {
+            Some(b'0'..=b'9') => Ok(()),
+            _ => Err(self.error(LexErrorKind::MissingDigits, start, self.cursor)),
+        }
+    }
+
+    // Tokenize the number literal from `start` up to the cursor. Returns false if
+    // the literal is invalid and lexing should stop.
+    fn push_number(&mut self, start: usize, radix: u32, float: bool) -> bool {
+        let text: String = self.raw[start..self.cursor]
+            .iter()
+            .filter(|b| **b != b'_')
+            .map(|b| *b as char)
+            .collect();
+
+        let parsed = if float {
+            match text.parse::<f64>() {
+                Ok(value) if value.is_finite() => Ok(TokenType::Float(value)),
+                _ => Err(LexErrorKind::NumberOverflow),
+            }
+        } else {
+            // skip the radix prefix.
+            let digits = if radix == 10 { &text[..] } else { &text[2..] };
+            if digits.is_empty() {
+                Err(LexErrorKind::MissingDigits)
+            } else {
+                // digits were checked while lexing, so the only failure left is overflow.
+                i64::from_str_radix(digits, radix)
+                    .map(TokenType::Integer)
+                    .map_err(|_| LexErrorKind::NumberOverflow)
+            }
+        };
+
+        match parsed {
+            Ok(token_type) => {
+                self.push(Token {
+                    token_type,
+                    span: self.span(start, self.cursor),
+                });
+                true
+            }
+            Err(kind) => {
+                let error = self.error(kind, start, self.cursor);
+                self.recover(error, start)
+            }
         }
     }
 
-    pub fn tokenize(&mut self) {
-        let mut mode = Mode::Normal;
-        let mut str_start = 0;
-        let mut int_start = 0;
-        let mut float_start = 0;
-        let mut id_start  = 0;
+    // Length in bytes of the identifier character under the cursor, None if it
+    // can't be part of an identifier. Identifiers follow the unicode XID rules:
+    // they start with an XID_Start character or _, and continue with XID_Continue.
+    fn identifier_char(&self, first: bool) -> Option<usize> {
+        let byte = self.raw[self.cursor];
+        if byte.is_ascii() {
+            let valid =
+                byte == b'_' || byte.is_ascii_alphabetic() || (!first && byte.is_ascii_digit());
+            return valid.then_some(1);
+        }
+        let (c, len) = decode_char(self.raw, self.cursor)?;
+        let valid = if first { is_xid_start(c) } else { is_xid_continue(c) };
+        valid.then_some(len)
+    }
 
+    // Tokenize the identifier/keyword from `start` up to the cursor. Returns false
+    // if it is a reserved word and lexing should stop.
+    fn push_identifier(&mut self, start: usize) -> bool {
+        let bytes: &'a [u8] = &self.raw[start..self.cursor];
+        // NFC normalize so visually identical names compare equal. ascii is
+        // already normalized, so ascii words (all keywords among them) are
+        // looked up without a copy and only names get one.
+        let name: Cow<'a, [u8]> = if bytes.is_ascii() {
+            Cow::Borrowed(bytes)
+        } else {
+            // only valid utf-8 makes it into an identifier.
+            let text = std::str::from_utf8(bytes).unwrap();
+            Cow::Owned(text.nfc().collect::<String>().into_bytes())
+        };
+        let token_type = match get_token(&name) {
+            // Keyword
+            Some(t) => t,
+            // reserved word, the whole word becomes the Error token.
+            None if is_reserved(&name) => {
+                // reserved words are all ascii.
+                let word = String::from_utf8_lossy(&name).into_owned();
+                let error = self.error(LexErrorKind::ReservedKeyword(word), start, self.cursor);
+                if !self.report(error) {
+                    return false;
+                }
+                TokenType::Error
+            }
+            // identifier
+            None => TokenType::Identifier(name.into_owned()),
+        };
+        self.push(Token {
+            token_type,
+            span: self.span(start, self.cursor),
+        });
+        true
+    }
+
+    // Queue `token` and keep track of whether it leaves a logical line open
+    // that still needs a Newline, or one that goes on at the next line.
+    // Comments don't count either way.
+    fn push(&mut self, token: Token) {
+        match token.token_type {
+            TokenType::Newline | TokenType::Indent | TokenType::Dedent => {
+                self.in_line = false;
+                self.continues = false;
+            }
+            TokenType::Comment => {}
+            _ => {
+                self.in_line = true;
+                self.continues = continues_line(&token.token_type);
+            }
+        }
+        self.pending.push_back(token);
+    }
+
+    // Layout pass for the start of a line: measures the leading whitespace and
+    // emits Indent/Dedent tokens against the indentation stack, Python style.
+    // Blank lines don't take part. Returns false if lexing should stop.
+    fn indentation(&mut self) -> bool {
+        let start = self.cursor;
+        let mut spaces = false;
+        let mut tabs = false;
         while self.cursor < self.raw.len() {
-            match mode {
-                // Normal mode checks each char and tokenize them Or changes the
-                // Mode to something else accordingly yo handle conplex tokens.
-                Mode::Normal => {
-                    match self.raw[self.cursor] {
-                        // increase the line number in self.line.
-                        // IMPORTANT: make sure all the other modes doesn't consume the
-                        // newline character to preserve your sanity later on while debugging.
-                        b'\n' => {
-                            self.line += 1;
-                            self.cursor += 1;
-                        }
+            match self.raw[self.cursor] {
+                b' ' => spaces = true,
+                b'\t' => tabs = true,
+                _ => break,
+            }
+            self.cursor += 1;
+        }
 
-                        // ignore space and tabs
-                        b' ' | b'\t' => {
-                            self.cursor += 1;
-                        }
+        // blank and comment only lines don't change the indentation.
+        let rest = &self.raw[self.cursor..];
+        if matches!(rest.first(), None | Some(b'\n') | Some(b'\r') | Some(b'#')) {
+            return true;
+        }
+        if rest.starts_with(b"/*") && only_block_comment(rest) {
+            return true;
+        }
+        // neither does a line starting with the } of a braced block, the } itself
+        // goes back to the indentation at its {.
+        if rest.first() == Some(&b'}') && !self.braces.is_empty() {
+            return true;
+        }
 
-                        // tokenize simple 1-2 character tokens.
-                        // =, +=, -=
-                        b'=' => {
-                            if self.raw[self.cursor + 1] == b'=' {
-                                self.tokens.push(Token {
-                                    token_type: TokenType::Equal,
-                                    start: self.cursor,
-                                    end: self.cursor + 1,
-                                    line: self.line,
-                                });
-                                self.cursor += 2;
-                            } else {
-                                self.tokens.push(Token {
-                                    token_type: TokenType::Assign,
-                                    start: self.cursor,
-                                    end: self.cursor + 1,
-                                    line: self.line,
-                                });
-                                self.cursor += 1;
-                            }
-                        }
+        let width = self.cursor - start;
+        if width > 0 {
+            let used = if tabs { b'\t' } else { b' ' };
+            let mixed = (spaces && tabs) || self.indent_char.is_some_and(|c| c != used);
+            self.indent_char.get_or_insert(used);
+            if mixed {
+                let error = self.error(LexErrorKind::MixedIndentation, start, self.cursor);
+                if !self.report(error) {
+                    return false;
+                }
+            }
+        }
 
-                        b'+' => {
-                            if self.raw[self.cursor + 1] == b'=' {
-                                self.tokens.push(Token {
-                                    token_type: TokenType::PlusAssign,
-                                    start: self.cursor,
-                                    end: self.cursor + 1,
-                                    line: self.line,
-                                });
-                                self.cursor += 2;
-                            } else {
-                                self.tokens.push(Token {
-                                    token_type: TokenType::Plus,
-                                    start: self.cursor,
-                                    end: self.cursor + 1,
-                                    line: self.line,
-                                });
-                                self.cursor += 1;
-                            }
-                        }
+        let (low, high) = *self.indents.last().unwrap();
+        if width > high {
+            self.indents.push((width, width));
+            self.push(Token {
+                token_type: TokenType::Indent,
+                span: self.span(start, self.cursor),
+            });
+        } else if width < low {
+            while self.indents.last().unwrap().0 > width {
+                self.indents.pop();
+                self.push(Token {
+                    token_type: TokenType::Dedent,
+                    span: self.span(self.cursor, self.cursor),
+                });
+            }
+            // dedented to a level that was never opened. the line is treated as
+            // part of the enclosing level, and so are later lines at the same
+            // width, so the error is reported once instead of on every line.
+            let level = self.indents.last_mut().unwrap();
+            if level.1 < width {
+                level.1 = width;
+                let error = self.error(LexErrorKind::InconsistentDedent, start, self.cursor);
+                return self.report(error);
+            }
+        }
+        true
+    }
 
-                        b'-' => {
-                            if self.raw[self.cursor + 1] == b'=' {
-                                self.tokens.push(Token {
-                                    token_type: TokenType::MinusAssign,
-                                    start: self.cursor,
-                                    end: self.cursor + 1,
-                                    line: self.line,
-                                });
-                                self.cursor += 2;
-                            } else {
-                                self.tokens.push(Token {
-                                    token_type: TokenType::Minus,
-                                    start: self.cursor,
-                                    end: self.cursor + 1,
-                                    line: self.line,
-                                });
-                                self.cursor += 1;
-                            }
-                        }
+    // One step of the state machine. Returns false if lexing should stop.
+    fn step(&mut self) -> bool {
+        match self.mode {
+            // Normal mode checks each char and tokenize them Or changes the
+            // Mode to something else accordingly yo handle conplex tokens.
+            Mode::Normal => {
+                // handle indentation before anything else on a new line.
+                if self.at_line_start {
+                    self.at_line_start = false;
+                    if !self.indentation() {
+                        return false;
+                    }
+                    return true;
+                }
 
-                        b'<' => {
-                            if self.raw[self.cursor + 1] == b'=' {
-                                self.tokens.push(Token {
-                                    token_type: TokenType::LessEqual,
-                                    start: self.cursor,
-                                    end: self.cursor + 1,
-                                    line: self.line,
-                                });
-                                self.cursor += 2;
-                            } else {
-                                self.tokens.push(Token {
-                                    token_type: TokenType::Less,
-                                    start: self.cursor,
-                                    end: self.cursor + 1,
-                                    line: self.line,
+                match self.raw[self.cursor] {
+                    // increase the line number in self.line.
+                    // IMPORTANT: make sure all the other modes doesn't consume the
+                    // newline character to preserve your sanity later on while debugging.
+                    b'\n' => {
+                        // newlines inside ( and [ don't end the logical line,
+                        // neither do newlines after an operator or comma.
+                        if self.nesting == 0 && !self.continues {
+                            if self.in_line {
+                                self.push(Token {
+                                    token_type: TokenType::Newline,
+                                    span: self.span(self.cursor, self.cursor + 1),
                                 });
-                                self.cursor += 1;
                             }
+                            self.at_line_start = true;
                         }
+                        self.line += 1;
+                        self.cursor += 1;
+                        self.line_start = self.cursor;
+                    }
 
-                        b'>' => {
-                            if self.raw[self.cursor + 1] == b'=' {
-                                self.tokens.push(Token {
-                                    token_type: TokenType::GreaterEqual,
-                                    start: self.cursor,
-                                    end: self.cursor + 1,
-                                    line: self.line,
-                                });
-                                self.cursor += 2;
-                            } else {
-                                self.tokens.push(Token {
-                                    token_type: TokenType::Greater,
-                                    start: self.cursor,
-                                    end: self.cursor + 1,
-                                    line: self.line,
-                                });
-                                self.cursor += 1;
-                            }
-                        }
+                    // ignore space, tabs and carriage returns.
+                    b' ' | b'\t' | b'\r' => {
+                        self.cursor += 1;
+                    }
 
-                        b'*' => {
-                            self.tokens.push(Token {
-                                token_type: TokenType::Asterisk,
-                                start: self.cursor,
-                                end: self.cursor + 1,
-                                line: self.line,
+                    // tokenize simple 1-2 character tokens.
+                    // =, ==, =>
+                    b'=' => {
+                        if self.peek_byte(1) == Some(b'=') {
+                            self.push(Token {
+                                token_type: TokenType::Equal,
+                                span: self.span(self.cursor, self.cursor + 2),
+                            });
+                            self.cursor += 2;
+                        } else if self.peek_byte(1) == Some(b'>') {
+                            self.push(Token {
+                                token_type: TokenType::FatArrow,
+                                span: self.span(self.cursor, self.cursor + 2),
+                            });
+                            self.cursor += 2;
+                        } else {
+                            self.push(Token {
+                                token_type: TokenType::Assign,
+                                span: self.span(self.cursor, self.cursor + 1),
                             });
                             self.cursor += 1;
                         }
+                    }
 
-                        b'/' => {
-                            self.tokens.push(Token {
-                                token_type: TokenType::Slash,
-                                start: self.cursor,
-                                end: self.cursor + 1,
-                                line: self.line,
+                    b'+' => {
+                        if self.peek_byte(1) == Some(b'=') {
+                            self.push(Token {
+                                token_type: TokenType::PlusAssign,
+                                span: self.span(self.cursor, self.cursor + 2),
+                            });
+                            self.cursor += 2;
+                        } else {
+                            self.push(Token {
+                                token_type: TokenType::Plus,
+                                span: self.span(self.cursor, self.cursor + 1),
                             });
                             self.cursor += 1;
                         }
+                    }
 
-                        b',' => {
-                            self.tokens.push(Token {
-                                token_type: TokenType::Comma,
-                                start: self.cursor,
-                                end: self.cursor + 1,
-                                line: self.line,
+                    // -, -=, ->
+                    b'-' => {
+                        if self.peek_byte(1) == Some(b'=') {
+                            self.push(Token {
+                                token_type: TokenType::MinusAssign,
+                                span: self.span(self.cursor, self.cursor + 2),
+                            });
+                            self.cursor += 2;
+                        } else if self.peek_byte(1) == Some(b'>') {
+                            self.push(Token {
+                                token_type: TokenType::Arrow,
+                                span: self.span(self.cursor, self.cursor + 2),
+                            });
+                            self.cursor += 2;
+                        } else {
+                            self.push(Token {
+                                token_type: TokenType::Minus,
+                                span: self.span(self.cursor, self.cursor + 1),
                             });
                             self.cursor += 1;
                         }
+                    }
 
-                        b';' => {
-                            self.tokens.push(Token {
-                                token_type: TokenType::Semicolon,
-                                start: self.cursor,
-                                end: self.cursor + 1,
-                                line: self.line,
+                    // <, <=, <<
+                    b'<' => {
+                        if self.peek_byte(1) == Some(b'=') {
+                            self.push(Token {
+                                token_type: TokenType::LessEqual,
+                                span: self.span(self.cursor, self.cursor + 2),
+                            });
+                            self.cursor += 2;
+                        } else if self.peek_byte(1) == Some(b'<') {
+                            self.push(Token {
+                                token_type: TokenType::ShiftLeft,
+                                span: self.span(self.cursor, self.cursor + 2),
+                            });
+                            self.cursor += 2;
+                        } else {
+                            self.push(Token {
+                                token_type: TokenType::Less,
+                                span: self.span(self.cursor, self.cursor + 1),
                             });
                             self.cursor += 1;
                         }
+                    }
 
-                        b':' => {
-                            self.tokens.push(Token {
-                                token_type: TokenType::Colon,
-                                start: self.cursor,
-                                end: self.cursor + 1,
-                                line: self.line,
+                    // >, >=, >>
+                    b'>' => {
+                        if self.peek_byte(1) == Some(b'=') {
+                            self.push(Token {
+                                token_type: TokenType::GreaterEqual,
+                                span: self.span(self.cursor, self.cursor + 2),
+                            });
+                            self.cursor += 2;
+                        } else if self.peek_byte(1) == Some(b'>') {
+                            self.push(Token {
+                                token_type: TokenType::ShiftRight,
+                                span: self.span(self.cursor, self.cursor + 2),
+                            });
+                            self.cursor += 2;
+                        } else {
+                            self.push(Token {
+                                token_type: TokenType::Greater,
+                                span: self.span(self.cursor, self.cursor + 1),
                             });
                             self.cursor += 1;
                         }
+                    }
 
-                        b'(' => {
-                            self.tokens.push(Token {
-                                token_type: TokenType::LeftParen,
-                                start: self.cursor,
-                                end: self.cursor + 1,
-                                line: self.line,
+                    // *, *=, **, **=
+                    b'*' => {
+                        if self.peek_byte(1) == Some(b'*') && self.peek_byte(2) == Some(b'=') {
+                            self.push(Token {
+                                token_type: TokenType::DoubleAsteriskAssign,
+                                span: self.span(self.cursor, self.cursor + 3),
+                            });
+                            self.cursor += 3;
+                        } else if self.peek_byte(1) == Some(b'*') {
+                            self.push(Token {
+                                token_type: TokenType::DoubleAsterisk,
+                                span: self.span(self.cursor, self.cursor + 2),
+                            });
+                            self.cursor += 2;
+                        } else if self.peek_byte(1) == Some(b'=') {
+                            self.push(Token {
+                                token_type: TokenType::AsteriskAssign,
+                                span: self.span(self.cursor, self.cursor + 2),
+                            });
+                            self.cursor += 2;
+                        } else {
+                            self.push(Token {
+                                token_type: TokenType::Asterisk,
+                                span: self.span(self.cursor, self.cursor + 1),
                             });
                             self.cursor += 1;
                         }
+                    }
 
-                        b')' => {
-                            self.tokens.push(Token {
-                                token_type: TokenType::RightParen,
-                                start: self.cursor,
-                                end: self.cursor + 1,
-                                line: self.line,
+                    // %, %=
+                    b'%' => {
+                        if self.peek_byte(1) == Some(b'=') {
+                            self.push(Token {
+                                token_type: TokenType::PercentAssign,
+                                span: self.span(self.cursor, self.cursor + 2),
+                            });
+                            self.cursor += 2;
+                        } else {
+                            self.push(Token {
+                                token_type: TokenType::Percent,
+                                span: self.span(self.cursor, self.cursor + 1),
                             });
                             self.cursor += 1;
                         }
+                    }
+
+                    // Enter Comment mode for # line comments and /* */ block comments.
+                    b'#' => {
+                        self.mode = Mode::Comment;
+                        self.comment_start = self.cursor;
+                        self.comment_line = self.line;
+                        self.comment_column = self.column(self.cursor);
+                        self.comment_depth = 0;
+                        self.cursor += 1;
+                    }
+
+                    b'/' if self.peek_byte(1) == Some(b'*') => {
+                        self.mode = Mode::Comment;
+                        self.comment_start = self.cursor;
+                        self.comment_line = self.line;
+                        self.comment_column = self.column(self.cursor);
+                        self.comment_depth = 1;
+                        self.cursor += 2;
+                    }
 
-                        b'{' => {
-                            self.tokens.push(Token {
-                                token_type: TokenType::LeftBrace,
-                                start: self.cursor,
-                                end: self.cursor + 1,
-                                line: self.line,
+                    // /, /=, // (integer division), //=.
+                    // NOTE: # and /* */ are the only comments, // is integer division.
+                    b'/' => {
+                        if self.peek_byte(1) == Some(b'/') && self.peek_byte(2) == Some(b'=') {
+                            self.push(Token {
+                                token_type: TokenType::DoubleSlashAssign,
+                                span: self.span(self.cursor, self.cursor + 3),
+                            });
+                            self.cursor += 3;
+                        } else if self.peek_byte(1) == Some(b'/') {
+                            self.push(Token {
+                                token_type: TokenType::DoubleSlash,
+                                span: self.span(self.cursor, self.cursor + 2),
+                            });
+                            self.cursor += 2;
+                        } else if self.peek_byte(1) == Some(b'=') {
+                            self.push(Token {
+                                token_type: TokenType::SlashAssign,
+                                span: self.span(self.cursor, self.cursor + 2),
+                            });
+                            self.cursor += 2;
+                        } else {
+                            self.push(Token {
+                                token_type: TokenType::Slash,
+                                span: self.span(self.cursor, self.cursor + 1),
                             });
                             self.cursor += 1;
                         }
+                    }
 
-                        b'}' => {
-                            self.tokens.push(Token {
-                                token_type: TokenType::RightBrace,
-                                start: self.cursor,
-                                end: self.cursor + 1,
-                                line: self.line,
+                    b',' => {
+                        self.push(Token {
+                            token_type: TokenType::Comma,
+                            span: self.span(self.cursor, self.cursor + 1),
+                        });
+                        self.cursor += 1;
+                    }
+
+                    b';' => {
+                        self.push(Token {
+                            token_type: TokenType::Semicolon,
+                            span: self.span(self.cursor, self.cursor + 1),
+                        });
+                        self.cursor += 1;
+                    }
+
+                    // :, ::
+                    b':' => {
+                        if self.peek_byte(1) == Some(b':') {
+                            self.push(Token {
+                                token_type: TokenType::DoubleColon,
+                                span: self.span(self.cursor, self.cursor + 2),
+                            });
+                            self.cursor += 2;
+                        } else {
+                            self.push(Token {
+                                token_type: TokenType::Colon,
+                                span: self.span(self.cursor, self.cursor + 1),
                             });
                             self.cursor += 1;
                         }
+                    }
 
-                        b'[' => {
-                            self.tokens.push(Token {
-                                token_type: TokenType::LeftSquare,
-                                start: self.cursor,
-                                end: self.cursor + 1,
-                                line: self.line,
-                            });
-                            self.cursor += 1;
+                    b'?' => {
+                        self.push(Token {
+                            token_type: TokenType::Question,
+                            span: self.span(self.cursor, self.cursor + 1),
+                        });
+                        self.cursor += 1;
+                    }
+
+                    b'(' => {
+                        self.push(Token {
+                            token_type: TokenType::LeftParen,
+                            span: self.span(self.cursor, self.cursor + 1),
+                        });
+                        self.cursor += 1;
+                        self.nesting += 1;
+                    }
+
+                    b')' => {
+                        self.push(Token {
+                            token_type: TokenType::RightParen,
+                            span: self.span(self.cursor, self.cursor + 1),
+                        });
+                        self.cursor += 1;
+                        self.nesting = self.nesting.saturating_sub(1);
+                    }
+
+                    b'{' => {
+                        self.push(Token {
+                            token_type: TokenType::LeftBrace,
+                            span: self.span(self.cursor, self.cursor + 1),
+                        });
+                        self.cursor += 1;
+                        self.braces.push(self.indents.len());
+                    }
+
+                    // close the indentation levels opened inside the braces
+                    // before the }, so the Dedents end up inside the block.
+                    b'}' => {
+                        if let Some(depth) = self.braces.pop() {
+                            while self.indents.len() > depth {
+                                self.indents.pop();
+                                self.push(Token {
+                                    token_type: TokenType::Dedent,
+                                    span: self.span(self.cursor, self.cursor),
+                                });
+                            }
                         }
+                        self.push(Token {
+                            token_type: TokenType::RightBrace,
+                            span: self.span(self.cursor, self.cursor + 1),
+                        });
+                        self.cursor += 1;
+                    }
+
+                    b'[' => {
+                        self.push(Token {
+                            token_type: TokenType::LeftSquare,
+                            span: self.span(self.cursor, self.cursor + 1),
+                        });
+                        self.cursor += 1;
+                        self.nesting += 1;
+                    }
 
-                        b']' => {
-                            self.tokens.push(Token {
-                                token_type: TokenType::RightSquare,
-                                start: self.cursor,
-                                end: self.cursor + 1,
-                                line: self.line,
+                    b']' => {
+                        self.push(Token {
+                            token_type: TokenType::RightSquare,
+                            span: self.span(self.cursor, self.cursor + 1),
+                        });
+                        self.cursor += 1;
+                        self.nesting = self.nesting.saturating_sub(1);
+                    }
+
+                    
+                    // & and | are bitwise, && and || are the short-circuit logical operators.
+                    b'&' => {
+                        if self.peek_byte(1) == Some(b'&') {
+                            self.push(Token {
+                                token_type: TokenType::AndAnd,
+                                span: self.span(self.cursor, self.cursor + 2),
+                            });
+                            self.cursor += 2;
+                        } else {
+                            self.push(Token {
+                                token_type: TokenType::BitAnd,
+                                span: self.span(self.cursor, self.cursor + 1),
                             });
                             self.cursor += 1;
                         }
+                    }
 
-                        
-                        b'&' => {
-                            self.tokens.push(Token {
-                                token_type: TokenType::And,
-                                start: self.cursor,
-                                end: self.cursor + 1,
-                                line: self.line,
+                    b'|' => {
+                        if self.peek_byte(1) == Some(b'|') {
+                            self.push(Token {
+                                token_type: TokenType::OrOr,
+                                span: self.span(self.cursor, self.cursor + 2),
+                            });
+                            self.cursor += 2;
+                        } else {
+                            self.push(Token {
+                                token_type: TokenType::BitOr,
+                                span: self.span(self.cursor, self.cursor + 1),
                             });
                             self.cursor += 1;
                         }
+                    }
 
- 
-                        b'|' => {
-                            self.tokens.push(Token {
-                                token_type: TokenType::Or,
-                                start: self.cursor,
-                                end: self.cursor + 1,
-                                line: self.line,
+                    b'^' => {
+                        self.push(Token {
+                            token_type: TokenType::Caret,
+                            span: self.span(self.cursor, self.cursor + 1),
+                        });
+                        self.cursor += 1;
+                    }
+
+                    b'~' => {
+                        self.push(Token {
+                            token_type: TokenType::Tilde,
+                            span: self.span(self.cursor, self.cursor + 1),
+                        });
+                        self.cursor += 1;
+                    }
+
+                    // !, !=
+                    b'!' => {
+                        if self.peek_byte(1) == Some(b'=') {
+                            self.push(Token {
+                                token_type: TokenType::NotEqual,
+                                span: self.span(self.cursor, self.cursor + 2),
+                            });
+                            self.cursor += 2;
+                        } else {
+                            self.push(Token {
+                                token_type: TokenType::Bang,
+                                span: self.span(self.cursor, self.cursor + 1),
                             });
                             self.cursor += 1;
                         }
+                    }
 
- 
-                        b'!' => {
-                            self.tokens.push(Token {
-                                token_type: TokenType::Bang,
-                                start: self.cursor,
-                                end: self.cursor + 1,
-                                line: self.line,
+
+                    // handle conplex tokens like String, Integer etc. by changing
+                    // the mode accordingly.
+                    
+                    // Enter String mode.
+                    b'"' => {
+                        self.mode = Mode::StringLiteral;
+                        self.str_line = self.line;
+                        self.str_column = self.column(self.cursor);
+                        self.str_start = self.cursor;
+                        self.cursor += 1;
+                        self.str_value.clear();
+                    }
+                    
+                    // Enter Integer mode.
+                    b'0'..=b'9' => {
+                        self.mode = Mode::Integer;
+                        self.int_start = self.cursor;
+                        self.radix = 10;
+                        self.cursor += 1;
+                    }
+
+                    // Enter Float mode for floats with a leading dot like .5
+                    b'.' if matches!(self.peek_byte(1), Some(b'0'..=b'9')) => {
+                        self.mode = Mode::Float;
+                        self.float_start = self.cursor;
+                        self.exponent = false;
+                        self.cursor += 1;
+                    }
+
+                    // ., .., ..=
+                    b'.' => {
+                        if self.peek_byte(1) == Some(b'.') && self.peek_byte(2) == Some(b'=') {
+                            self.push(Token {
+                                token_type: TokenType::DotDotEq,
+                                span: self.span(self.cursor, self.cursor + 3),
+                            });
+                            self.cursor += 3;
+                        } else if self.peek_byte(1) == Some(b'.') {
+                            self.push(Token {
+                                token_type: TokenType::DotDot,
+                                span: self.span(self.cursor, self.cursor + 2),
+                            });
+                            self.cursor += 2;
+                        } else {
+                            self.push(Token {
+                                token_type: TokenType::Dot,
+                                span: self.span(self.cursor, self.cursor + 1),
                             });
                             self.cursor += 1;
                         }
+                    }
+                    
+                    // Enter Identifier Mode for a letter or _, anything else is unknown.
+                    // Note: Identifier Mode also handles keywords.
+                    _ => {
+                        if let Some(len) = self.identifier_char(true) {
+                            self.mode = Mode::Identifier;
+                            self.id_start = self.cursor;
+                            self.cursor += len;
+                        } else {
+                            // only the character itself is skipped, whatever follows
+                            // it is lexed as usual.
+                            let error = self.char_error(LexErrorKind::UnknownCharacter);
+                            let end = error.span.end;
+                            if !self.report(error) {
+                                return false;
+                            }
+                            self.push(Token {
+                                token_type: TokenType::Error,
+                                span: self.span(self.cursor, end),
+                            });
+                            self.cursor = end;
+                        }
+                    }
+                }
+            }
 
 
-                        // handle conplex tokens like String, Integer etc. by changing
-                        // the mode accordingly.
-                        
-                        // Enter String mode.
-                        b'"' => {
-                            mode = Mode::StringLiteral;
-                            self.cursor += 1;
-                            // Note: add starting position after the increase so the 
-                            // quote gets skipped.
-                            str_start = self.cursor;
+            // String Mode: Tokenize strings.
+            // NOTE: strings not closed by the end of the file are handled after the loop.
+            Mode::StringLiteral => {
+                if self.raw[self.cursor] == b'"' {
+                    let value = String::from_utf8_lossy(&self.str_value).into_owned();
+                    self.push(Token {
+                        token_type: TokenType::StringLiteral(value),
+                        span: self.string_span(self.cursor + 1),
+                    });
+                    self.cursor += 1;
+                    self.mode = Mode::Normal;
+                } else if self.raw[self.cursor] == b'\\' && self.cursor + 1 < self.raw.len() {
+                    // a backslash right before the end of the file is left for
+                    // the not closed string check below.
+                    let (decoded, len) = decode_escape(&self.raw[self.cursor..]);
+                    let c = match decoded {
+                        Ok(c) => c,
+                        Err(kind) => {
+                            let error = self.error(kind, self.cursor, self.cursor + len);
+                            if !self.report(error) {
+                                return false;
+                            }
+                            char::REPLACEMENT_CHARACTER
                         }
-                        
-                        // Enter Integer mode.
-                        b'0'..=b'9' => {
-                            mode = Mode::Integer;
-                            int_start = self.cursor;
-                            self.cursor += 1;
+                    };
+                    let mut buf = [0; 4];
+                    self.str_value.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                    // a \ before a newline is an unknown escape, but the newline
+                    // it consumed still starts a new line.
+                    if self.raw[self.cursor + 1] == b'\n' {
+                        self.line += 1;
+                        self.line_start = self.cursor + 2;
+                    }
+                    self.cursor += len;
+                } else if self.raw[self.cursor] >= 0x80 {
+                    // multi byte characters are copied whole, invalid utf-8 is an error.
+                    match decode_char(self.raw, self.cursor) {
+                        Some((_, len)) => {
+                            let end = self.cursor + len;
+                            self.str_value.extend_from_slice(&self.raw[self.cursor..end]);
+                            self.cursor = end;
                         }
-                        
-                        // Enter Identifier Mode.
-                        // Note: Identifier Mode also handles keywords.
-                        b'a'..=b'z' | b'A'..=b'Z' =>{
-                            mode = Mode::Identifier;
-                            id_start = self.cursor;
+                        None => {
+                            let at = self.cursor;
+                            let error = self.error(LexErrorKind::InvalidUtf8, at, at + 1);
+                            if !self.report(error) {
+                                return false;
+                            }
+                            let mut buf = [0; 4];
+                            let c = char::REPLACEMENT_CHARACTER.encode_utf8(&mut buf);
+                            self.str_value.extend_from_slice(c.as_bytes());
                             self.cursor += 1;
                         }
-
-                        _ => {
-                            // TODO: fix the getchar function for proper utf-8 decoding.
-                            println!(
-                                "Lexer: Unknown character '{}' at line {}",
-                                getchar_from_bytes(&self.raw, self.cursor),
-                                self.line
-                            );
-                            std::process::exit(1);
-                        }
                     }
+                } else {
+                    self.str_value.push(self.raw[self.cursor]);
+                    // strings can span multiple lines, keep the line count right.
+                    if self.raw[self.cursor] == b'\n' {
+                        self.line += 1;
+                        self.line_start = self.cursor + 1;
+                    }
+                    self.cursor += 1;
                 }
+            }
+            
+            // Integer Mode: tokenize Integers. and change mode to float if found dot.
+            // NOTE: integers ending the file are tokenized after the loop.
+            Mode::Integer => {
+                match self.raw[self.cursor] {
+                    // radix prefix right after a leading zero: 0x, 0o, 0b.
+                    b'x' | b'X' | b'o' | b'O' | b'b' | b'B'
+                        if self.cursor == self.int_start + 1 && self.raw[self.int_start] == b'0' =>
+                    {
+                        self.radix = match self.raw[self.cursor].to_ascii_lowercase() {
+                            b'x' => 16,
+                            b'o' => 8,
+                            _ => 2,
+                        };
+                        self.cursor += 1;
+                    }
 
+                    // a range like 1..5 ends the integer, the dots are left for Normal mode.
+                    b'.' if self.peek_byte(1) == Some(b'.') => {
+                        if !self.push_number(self.int_start, self.radix, false) {
+                            return false;
+                        }
+                        self.mode = Mode::Normal;
+                    }
 
-                // String Mode: Tokenize strings.
-                // TODO: add file ending edgecase.
-                Mode::StringLiteral => {
-                    if self.raw[self.cursor] == b'"' && self.raw[self.cursor-1] != b'\\' {
-                        self.tokens.push(Token {
-                            token_type: TokenType::StringLiteral,
-                            start: str_start,
-                            end: self.cursor - 1,
-                            line: self.line,
-                        });
-                        self.cursor += 1;
-                        mode = Mode::Normal;
-                    } else {
+                    // Enter float mode if encountered a dot in the integer.
+                    b'.' if self.radix == 10 => {
+                        self.mode = Mode::Float;
+                        self.float_start = self.int_start;
+                        self.exponent = false;
                         self.cursor += 1;
                     }
-                }
-                
-                // Integer Mode: tokenize Integers. and change mode to float if found dot.
-                // TODO: add eof edgecase.
-                Mode::Integer => {
-                    match self.raw[self.cursor] {
-                        // Enter float mode if encountered a dot in the integer.
-                        b'.' => {
-                            mode = Mode::Float;
-                            float_start = int_start;
-                            self.cursor += 1;
-                        }
-                        
-                        // Tokenize the integer after it ends with a new line.
-                        // IMPORTANT: Don't consume the newline just set the mode to Normal.
-                        // Normal mode needs the newline yo keep track of line numbers.
-                        b'\n' => {
-                            self.tokens.push(Token {
-                                token_type: TokenType::Integer,
-                                start: int_start,
-                                end: self.cursor - 1,
-                                line: self.line,
-                            });
-                            mode = Mode::Normal;
+
+                    // an exponent makes it a float as well.
+                    b'e' | b'E' if self.radix == 10 => {
+                        self.mode = Mode::Float;
+                        self.float_start = self.int_start;
+                        self.exponent = true;
+                        if let Err(error) = self.lex_exponent(self.float_start) {
+                            if !self.recover(error, self.float_start) {
+                                return false;
+                            }
+                            self.mode = Mode::Normal;
                         }
+                    }
 
-                        // Tokenize the Integer after it ends with a space.
-                        b' ' => {
-                            self.tokens.push(Token {
-                                token_type: TokenType::Integer,
-                                start: int_start,
-                                end: self.cursor - 1,
-                                line: self.line,
-                            });
-                            mode = Mode::Normal;
-                            self.cursor += 1;
+                    // Tokenize the integer once it ends with whitespace, an operator
+                    // or a delimiter.
+                    // IMPORTANT: Don't consume the character just set the mode to Normal.
+                    // Normal mode needs it, newlines especially to keep track of line numbers.
+                    c if ends_number(c) => {
+                        if !self.push_number(self.int_start, self.radix, false) {
+                            return false;
                         }
+                        self.mode = Mode::Normal;
+                    }
 
+                    // continue for valid digits of the radix and _ separators.
+                    c if c == b'_' || (c as char).is_digit(self.radix) => {
+                        self.cursor += 1;
+                    }
 
-                        // continue for valid numbers
-                        b'0'..=b'9' => {
-                            self.cursor += 1;
-                        }
-                        
-                        // exit for everything else.
-                        _ => {
-                            println!(
-                                "Lexer: invalid integer '{}' at line {}",
-                                getchar_from_bytes(&self.raw, self.cursor),
-                                self.line
-                            );
-                            std::process::exit(1);
+                    // exit for everything else.
+                    _ => {
+                        let error = self.char_error(LexErrorKind::InvalidInteger);
+                        if !self.recover(error, self.int_start) {
+                            return false;
                         }
+                        self.mode = Mode::Normal;
                     }
                 }
+            }
 
-                // Float Mode: tokenize Float.
-                // TODO: add eof edgecase.
-                Mode::Float => {
-                    match self.raw[self.cursor] {
-                        // First dot was already consumed to enter float mode.
-                        // the second one is invalid.
-                        b'.' => {
-                            println!(
-                                "Lexer: too many floating points for integer at line {}",
-                                self.line
-                            );
-                            std::process::exit(1);
-                        }
-                        
-                        // Tokenize the Float after it ends with a new line.
-                        // IMPORTANT: Don't consume the newline just set the mode to Normal.
-                        // Normal mode needs the newline yo keep track of line numbers.
-                        b'\n' => {
-                            self.tokens.push(Token {
-                                token_type: TokenType::Float,
-                                start: float_start,
-                                end: self.cursor - 1,
-                                line: self.line,
-                            });
-                            mode = Mode::Normal;
+            // Float Mode: tokenize Float.
+            // NOTE: floats ending the file are tokenized after the loop.
+            Mode::Float => {
+                match self.raw[self.cursor] {
+                    // a range like 1.5..2 ends the float.
+                    b'.' if self.peek_byte(1) == Some(b'.') => {
+                        if !self.push_number(self.float_start, 10, true) {
+                            return false;
                         }
+                        self.mode = Mode::Normal;
+                    }
 
-                        // Tokenize the Float after it ends with a space.
-                        b' ' => {
-                            self.tokens.push(Token {
-                                token_type: TokenType::Float,
-                                start: float_start,
-                                end: self.cursor - 1,
-                                line: self.line,
-                            });
-                            mode = Mode::Normal;
-                            self.cursor += 1;
+                    // First dot was already consumed to enter float mode.
+                    // the second one is invalid, so is any dot in the exponent.
+                    b'.' => {
+                        let error = if self.exponent {
+                            self.char_error(LexErrorKind::InvalidFloat)
+                        } else {
+                            self.error(
+                                LexErrorKind::TooManyDecimalPoints,
+                                self.cursor,
+                                self.cursor + 1,
+                            )
+                        };
+                        if !self.recover(error, self.float_start) {
+                            return false;
                         }
+                        self.mode = Mode::Normal;
+                    }
 
+                    b'e' | b'E' if !self.exponent => {
+                        self.exponent = true;
+                        if let Err(error) = self.lex_exponent(self.float_start) {
+                            if !self.recover(error, self.float_start) {
+                                return false;
+                            }
+                            self.mode = Mode::Normal;
+                        }
+                    }
 
-                        // continue for valid numbers
-                        b'0'..=b'9' => {
-                            self.cursor += 1;
+                    // Tokenize the Float once it ends with whitespace, an operator
+                    // or a delimiter.
+                    // IMPORTANT: Don't consume the character just set the mode to Normal.
+                    c if ends_number(c) => {
+                        if !self.push_number(self.float_start, 10, true) {
+                            return false;
                         }
-                        
-                        // exit for everything else.
-                        _ => {
-                            println!(
-                                "Lexer: invalid Float '{}' at line {}",
-                                getchar_from_bytes(&self.raw, self.cursor),
-                                self.line
-                            );
-                            std::process::exit(1);
+                        self.mode = Mode::Normal;
+                    }
+
+                    // continue for valid numbers and _ separators.
+                    b'0'..=b'9' | b'_' => {
+                        self.cursor += 1;
+                    }
+
+                    // exit for everything else.
+                    _ => {
+                        let error = self.char_error(LexErrorKind::InvalidFloat);
+                        if !self.recover(error, self.float_start) {
+                            return false;
                         }
+                        self.mode = Mode::Normal;
                     }
                 }
+            }
 
-                // Identifier Mode: Tokenize Identifiers and keywords.
-                Mode::Identifier => {
-                    match self.raw[self.cursor] {
-                        // NOTE: first character of the Identifier/Keyword has already been 
-                        // consumed by normal mode to enter Identifier mode. starting
-                        // from second character, valid identifier/Keyword name should only have 
-                        // a-z, A-Z, and 0-9 characters.
-                        b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => {
-                            self.cursor += 1;
-                        }
+            // Identifier Mode: Tokenize Identifiers and keywords.
+            Mode::Identifier => {
+                match self.identifier_char(false) {
+                    // NOTE: first character of the Identifier/Keyword has already been 
+                    // consumed by normal mode to enter Identifier mode. starting
+                    // from second character, valid identifier/Keyword name should only have 
+                    // XID_Continue characters, which include 0-9 and _.
+                    Some(len) => {
+                        self.cursor += len;
+                    }
 
-                        // Tokenize Identifier/keyword after encountering 
-                        // any other characters stated above.
-                        // IMPORTANT: don't consume the character. leave it for normal mode.
-                        _ => {
-                            let (token_type, _) = get_token(&self.raw[id_start..self.cursor-1]);
-                            match token_type {
-                                // Keyword
-                                Some(t) => {
-                                    self.tokens.push(Token {
-                                        token_type: t,
-                                        start: id_start,
-                                        end: self.cursor - 1,
-                                        line: self.line,
-                                    });
-                                    mode = Mode::Normal;
-                                }
-
-                                // identifier
-                                None => {
-                                    self.tokens.push(Token {
-                                        token_type: TokenType::Identifier(self.raw[id_start..self.cursor-1].to_vec()),
-                                        start: id_start,
-                                        end: self.cursor - 1,
-                                        line: self.line,
-                                    });
-                                    mode = Mode::Normal;
-                                }
-                            }
+                    // Tokenize Identifier/keyword after encountering 
+                    // any other characters stated above.
+                    // IMPORTANT: don't consume the character. leave it for normal mode.
+                    None => {
+                        if !self.push_identifier(self.id_start) {
+                            return false;
                         }
-                        
+                        self.mode = Mode::Normal;
                     }
                 }
+            }
 
-                _ => {
-                    println!("Lexer: State machine 'mode' got corrupted at runtime.");
-                    println!("Unknown mode: {:#?}", mode);
-                    std::process::exit(1);
+            // Comment Mode: skip comments, or tokenize them if asked to.
+            Mode::Comment => {
+                let rest = &self.raw[self.cursor..];
+                if self.comment_depth == 0 {
+                    // line comments end before the newline.
+                    // IMPORTANT: Don't consume the newline just set the mode to Normal.
+                    if rest[0] == b'\n' {
+                        if self.keep_comments {
+                            self.push(Token {
+                                token_type: TokenType::Comment,
+                                span: self.comment_span(self.cursor),
+                            });
+                        }
+                        self.mode = Mode::Normal;
+                    } else {
+                        self.cursor += 1;
+                    }
+                } else if rest.starts_with(b"/*") {
+                    // block comments nest.
+                    self.comment_depth += 1;
+                    self.cursor += 2;
+                } else if rest.starts_with(b"*/") {
+                    self.comment_depth -= 1;
+                    self.cursor += 2;
+                    if self.comment_depth == 0 {
+                        if self.keep_comments {
+                            self.push(Token {
+                                token_type: TokenType::Comment,
+                                span: self.comment_span(self.cursor),
+                            });
+                        }
+                        self.mode = Mode::Normal;
+                    }
+                } else {
+                    // block comments can span multiple lines, keep the line count right.
+                    if rest[0] == b'\n' {
+                        self.line += 1;
+                        self.line_start = self.cursor + 1;
+                    }
+                    self.cursor += 1;
                 }
             }
         }
+        true
+    }
 
-
+    // Tokenize whatever was left when the file ended, close the layout and queue Eof.
+    fn finish(&mut self) {
         // handle not closed ("String) StringLiteral.
         // the mode 'Mode::StringLiteral' should still be active if it was not closed.
-        if mode == Mode::StringLiteral {
-            println!("Lexer: StringLiteral did not close.");
-            std::process::exit(1);
+        if self.mode == Mode::StringLiteral {
+            // point at the opening quote rather than the end of the file.
+            self.errors.push(LexError {
+                kind: LexErrorKind::UnterminatedString,
+                span: self.string_span(self.raw.len()),
+            });
+            if self.recover {
+                self.push(Token {
+                    token_type: TokenType::Error,
+                    span: self.string_span(self.raw.len()),
+                });
+            }
+        }
+
+        // tokenize whatever was still pending when the file ended.
+        match self.mode {
+            Mode::Integer => {
+                self.push_number(self.int_start, self.radix, false);
+            }
+            Mode::Float => {
+                self.push_number(self.float_start, 10, true);
+            }
+            Mode::Identifier => {
+                self.push_identifier(self.id_start);
+            }
+            _ => {}
+        }
+
+        // line comments can end the file, block comments have to be closed.
+        if self.mode == Mode::Comment {
+            if self.comment_depth == 0 {
+                if self.keep_comments {
+                    self.push(Token {
+                        token_type: TokenType::Comment,
+                        span: self.comment_span(self.cursor),
+                    });
+                }
+            } else {
+                self.errors.push(LexError {
+                    kind: LexErrorKind::UnterminatedComment,
+                    span: self.comment_span(self.raw.len()),
+                });
+                if self.recover {
+                    self.push(Token {
+                        token_type: TokenType::Error,
+                        span: self.comment_span(self.raw.len()),
+                    });
+                }
+            }
+        }
+
+        // close the last logical line and every indentation level still open.
+        if self.in_line {
+            self.push(Token {
+                token_type: TokenType::Newline,
+                span: self.span(self.cursor, self.cursor),
+            });
+        }
+        while self.indents.len() > 1 {
+            self.indents.pop();
+            self.push(Token {
+                token_type: TokenType::Dedent,
+                span: self.span(self.cursor, self.cursor),
+            });
+        }
+
+        self.push(Token {
+            token_type: TokenType::Eof,
+            span: self.span(self.cursor, self.cursor),
+        });
+        self.finished = true;
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        self.fill(1);
+        self.pending.pop_front()
+    }
+}
+
+#[derive(Debug)]
+pub struct File {
+    raw: Vec<u8>,
+    tokens: Vec<Token>,
+    // emit Comment tokens instead of dropping comments.
+    keep_comments: bool,
+}
+
+impl File {
+    pub fn new(raw_data: Vec<u8>) -> Self {
+        Self {
+            raw: raw_data,
+            tokens: vec![],
+            keep_comments: false,
+        }
+    }
+
+    /// Emit comments as `TokenType::Comment` tokens instead of dropping them.
+    /// Useful for tooling like formatters that need to see them.
+    pub fn keep_comments(mut self, keep: bool) -> Self {
+        self.keep_comments = keep;
+        self
+    }
+
+    /// The raw source of this file.
+    pub fn source(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// Tokens from the last `tokenize` call, empty before that.
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    /// Tokens from the last `tokenize` call within the byte range `start..end`
+    /// of the source.
+    pub fn tokens_in(&self, start: usize, end: usize) -> &[Token] {
+        let from = self.tokens.partition_point(|t| t.span.start < start);
+        let to = self.tokens.partition_point(|t| t.span.start < end);
+        &self.tokens[from..to.max(from)]
+    }
+
+    /// Debug dump of the tokens from the last `tokenize` call.
+    pub fn dump_tokens(&self) -> String {
+        format!("{:#?}", self.tokens)
+    }
+
+    /// Line/column lookups for this file's source.
+    pub fn source_map(&self) -> SourceMap<'_> {
+        SourceMap::new(&self.raw)
+    }
+
+    /// A streaming lexer over this file. It stops at the first error, use
+    /// `Lexer::recovery` to keep going.
+    pub fn lexer(&self) -> Lexer<'_> {
+        Lexer::new(&self.raw).keep_comments(self.keep_comments)
+    }
+
+    /// Tokenize the whole file, stopping at the first lexical error. The file
+    /// keeps the tokens, the returned slice is the same as `tokens()`.
+    pub fn tokenize(&mut self) -> Result<&[Token], Vec<LexError>> {
+        let mut lexer = self.lexer();
+        let tokens: Vec<Token> = lexer.by_ref().collect();
+        let errors = lexer.errors().to_vec();
+        self.tokens = tokens;
+
+        if errors.is_empty() {
+            Ok(&self.tokens)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Tokenize the whole file, reporting every lexical error instead of stopping
+    /// at the first one. Bad input shows up as `TokenType::Error` in the tokens.
+    /// Like `tokenize`, the tokens stay in the file.
+    pub fn tokenize_with_recovery(&mut self) -> (&[Token], Vec<LexError>) {
+        let mut lexer = self.lexer().recovery(true);
+        let tokens: Vec<Token> = lexer.by_ref().collect();
+        let errors = lexer.errors().to_vec();
+        self.tokens = tokens;
+
+        (&self.tokens, errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(source: &str) -> Vec<Token> {
+        File::new(source.as_bytes().to_vec()).tokenize().unwrap().to_vec()
+    }
+
+    fn types(source: &str) -> Vec<TokenType> {
+        lex(source).into_iter().map(|t| t.token_type).collect()
+    }
+
+    fn ident(name: &str) -> TokenType {
+        TokenType::Identifier(name.as_bytes().to_vec())
+    }
+
+    fn lex_with_errors(source: &str) -> (Vec<TokenType>, Vec<(LexErrorKind, Span)>) {
+        let mut file = File::new(source.as_bytes().to_vec());
+        let (tokens, errors) = file.tokenize_with_recovery();
+        (
+            tokens.iter().map(|t| t.token_type.clone()).collect(),
+            errors.into_iter().map(|e| (e.kind, e.span)).collect(),
+        )
+    }
+
+    #[test]
+    fn empty_file_is_just_eof() {
+        assert_eq!(types(""), vec![TokenType::Eof]);
+        assert_eq!(types("\n\n"), vec![TokenType::Eof]);
+    }
+
+    #[test]
+    fn integer_at_eof() {
+        let tokens = lex("123");
+        assert_eq!(tokens[0].token_type, TokenType::Integer(123));
+        assert_eq!((tokens[0].span.start, tokens[0].span.end), (0, 3));
+        assert_eq!(tokens.last().unwrap().token_type, TokenType::Eof);
+    }
+
+    #[test]
+    fn float_at_eof() {
+        let tokens = lex("x = 1.25");
+        assert_eq!(tokens[2].token_type, TokenType::Float(1.25));
+        assert_eq!((tokens[2].span.start, tokens[2].span.end), (4, 8));
+    }
+
+    #[test]
+    fn identifier_and_keyword_at_eof() {
+        assert_eq!(
+            types("foo"),
+            vec![ident("foo"), TokenType::Newline, TokenType::Eof]
+        );
+        assert_eq!(
+            types("return"),
+            vec![TokenType::Return, TokenType::Newline, TokenType::Eof]
+        );
+    }
+
+    #[test]
+    fn keywords() {
+        use TokenType::*;
+        assert_eq!(
+            types("let const import from as class break continue nil in match"),
+            vec![Let, Const, Import, From, As, Class, Break, Continue, Nil, In, Match, Newline, Eof]
+        );
+        // only whole words are keywords.
+        assert_eq!(types("lets")[0], ident("lets"));
+    }
+
+    #[test]
+    fn keyword_table_buckets() {
+        for (len, bucket) in KEYWORDS.iter().enumerate() {
+            for (word, _) in bucket.iter() {
+                assert_eq!(word.len(), len, "{:?}", String::from_utf8_lossy(word));
+            }
+        }
+        assert_eq!(get_token(b"function"), Some(TokenType::Function));
+        assert_eq!(get_token(b"functions"), None);
+        assert_eq!(get_token(b""), None);
+        assert!(is_reserved(b"interface"));
+        assert!(!is_reserved(b"while"));
+    }
+
+    #[test]
+    fn reserved_keywords_are_not_names() {
+        let errors = File::new(b"yield = 1".to_vec()).tokenize().unwrap_err();
+        assert_eq!(errors[0].kind, LexErrorKind::ReservedKeyword("yield".to_string()));
+        assert_eq!((errors[0].span.start, errors[0].span.end), (0, 5));
+
+        let mut file = File::new(b"a = type\n".to_vec());
+        let (tokens, errors) = file.tokenize_with_recovery();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(tokens[2].token_type, TokenType::Error);
+        assert_eq!(tokens[3].token_type, TokenType::Newline);
+    }
+
+    #[test]
+    fn lookahead_operators_at_eof() {
+        for (source, expected) in [
+            ("=", TokenType::Assign),
+            ("+", TokenType::Plus),
+            ("-", TokenType::Minus),
+            ("<", TokenType::Less),
+            (">", TokenType::Greater),
+        ] {
+            assert_eq!(
+                types(source),
+                vec![expected, TokenType::Newline, TokenType::Eof],
+                "{source:?}"
+            );
         }
+    }
+
+    #[test]
+    fn eof_after_trailing_newline() {
+        assert_eq!(
+            types("a\n"),
+            vec![ident("a"), TokenType::Newline, TokenType::Eof]
+        );
+    }
+
+    #[test]
+    fn eof_closes_open_indentation() {
+        assert_eq!(
+            types("if a:\n  b"),
+            vec![
+                TokenType::If,
+                ident("a"),
+                TokenType::Colon,
+                TokenType::Newline,
+                TokenType::Indent,
+                ident("b"),
+                TokenType::Newline,
+                TokenType::Dedent,
+                TokenType::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn mixed_indentation() {
+        use TokenType::*;
+        // tabs on one line, spaces on a later one.
+        let (tokens, errors) = lex_with_errors("if a:\n\tb\n c\n");
+        assert_eq!(errors, vec![(LexErrorKind::MixedIndentation, Span::new(9, 10, 3, 1))]);
+        assert_eq!(
+            tokens,
+            vec![
+                If, ident("a"), Colon, Newline, Indent, ident("b"), Newline, ident("c"),
+                Newline, Dedent, Eof,
+            ]
+        );
+
+        // tabs and spaces on the same line.
+        let (tokens, errors) = lex_with_errors("if a:\n \tb\n");
+        assert_eq!(errors, vec![(LexErrorKind::MixedIndentation, Span::new(6, 8, 2, 1))]);
+        assert_eq!(
+            tokens,
+            vec![If, ident("a"), Colon, Newline, Indent, ident("b"), Newline, Dedent, Eof]
+        );
+    }
+
+    #[test]
+    fn inconsistent_dedent() {
+        use TokenType::*;
+        // the misaligned lines join the outer level and the error is reported once.
+        let (tokens, errors) = lex_with_errors("if a:\n    b\n  c\n  d\ne\n");
+        assert_eq!(errors, vec![(LexErrorKind::InconsistentDedent, Span::new(12, 14, 3, 1))]);
+        assert_eq!(
+            tokens,
+            vec![
+                If, ident("a"), Colon, Newline, Indent, ident("b"), Newline, Dedent, ident("c"),
+                Newline, ident("d"), Newline, ident("e"), Newline, Eof,
+            ]
+        );
+
+        // the same inside a nested block, dedenting from it still works.
+        let (tokens, errors) = lex_with_errors("if a:\n  if b:\n      c\n    d\n  e\nf\n");
+        assert_eq!(errors, vec![(LexErrorKind::InconsistentDedent, Span::new(22, 26, 4, 1))]);
+        assert_eq!(
+            tokens,
+            vec![
+                If, ident("a"), Colon, Newline, Indent, If, ident("b"), Colon, Newline, Indent,
+                ident("c"), Newline, Dedent, ident("d"), Newline, ident("e"), Newline, Dedent,
+                ident("f"), Newline, Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn closing_brace_closes_its_indentation() {
+        use TokenType::*;
+        // the Dedent comes before the }, wherever the } is indented.
+        for source in ["if a {\n  b\n}\nc", "if a {\n  b\n  }\nc", "if a {\n  b\n    }\nc"] {
+            assert_eq!(
+                types(source),
+                vec![
+                    If, ident("a"), LeftBrace, Newline, Indent, ident("b"), Newline, Dedent,
+                    RightBrace, Newline, ident("c"), Newline, Eof,
+                ],
+                "{source:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn trailing_operators_continue_the_line() {
+        use TokenType::*;
+        assert_eq!(
+            types("a = 1 +\n      2\nb"),
+            vec![ident("a"), Assign, Integer(1), Plus, Integer(2), Newline, ident("b"), Newline, Eof]
+        );
+        // comments and blank lines in between don't matter.
+        assert_eq!(
+            types("x = a and # more\n\n  b"),
+            vec![ident("x"), Assign, ident("a"), And, ident("b"), Newline, Eof]
+        );
+        // but these can end a line.
+        assert_eq!(types("a?\nb")[2], Newline);
+        assert_eq!(types("f()\nb")[3], Newline);
+        assert_eq!(types("if a:\n  b")[3], Newline);
+    }
+
+    #[test]
+    fn eof_span_is_empty_at_end() {
+        let tokens = lex("ab");
+        let eof = tokens.last().unwrap();
+        assert_eq!((eof.span.start, eof.span.end), (2, 2));
+    }
+
+    #[test]
+    fn recovery_still_ends_in_eof() {
+        let mut file = File::new(b"a $".to_vec());
+        let (tokens, errors) = file.tokenize_with_recovery();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(tokens.last().unwrap().token_type, TokenType::Eof);
+    }
+
+    #[test]
+    fn recovery_keeps_the_tokens_after_an_error() {
+        let (tokens, errors) = lex_with_errors("a = $x+1\nb = 12ab+3\nc = 1.2.3*d\n");
+        assert_eq!(
+            errors,
+            vec![
+                (LexErrorKind::UnknownCharacter('$'), Span::new(4, 5, 1, 5)),
+                (LexErrorKind::InvalidInteger('a'), Span::new(15, 16, 2, 7)),
+                (LexErrorKind::TooManyDecimalPoints, Span::new(27, 28, 3, 8)),
+            ]
+        );
+
+        use TokenType::*;
+        assert_eq!(
+            tokens,
+            vec![
+                ident("a"), Assign, Error, ident("x"), Plus, Integer(1), Newline,
+                ident("b"), Assign, Error, Plus, Integer(3), Newline,
+                ident("c"), Assign, Error, Asterisk, ident("d"), Newline,
+                Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_have_kind_and_position() {
+        let cases: &[(&str, LexErrorKind, Span)] = &[
+            ("x = $", LexErrorKind::UnknownCharacter('$'), Span::new(4, 5, 1, 5)),
+            ("a\n  π = @", LexErrorKind::UnknownCharacter('@'), Span::new(9, 10, 2, 7)),
+            ("y = 0b12", LexErrorKind::InvalidInteger('2'), Span::new(7, 8, 1, 8)),
+            ("\nz = 1.2.3", LexErrorKind::TooManyDecimalPoints, Span::new(8, 9, 2, 8)),
+            ("s = \"abc", LexErrorKind::UnterminatedString, Span::new(4, 8, 1, 5)),
+        ];
+        for (source, kind, span) in cases {
+            let (_, errors) = lex_with_errors(source);
+            assert_eq!(errors, vec![(kind.clone(), *span)], "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn error_display_has_line_and_column() {
+        let errors = File::new(b"a\n  $".to_vec()).tokenize().unwrap_err();
+        assert_eq!(errors[0].to_string(), "Unknown character '$' at line 2, column 3");
+
+        // control characters are escaped instead of breaking the line.
+        for (kind, message) in [
+            (LexErrorKind::UnknownEscape('\n'), r"unknown escape sequence '\\n'"),
+            (LexErrorKind::UnknownCharacter('\u{7}'), r"Unknown character '\u{7}'"),
+            (LexErrorKind::InvalidInteger('\t'), r"invalid integer '\t'"),
+            (LexErrorKind::InvalidFloat('\r'), r"invalid Float '\r'"),
+        ] {
+            assert_eq!(kind.to_string(), message);
+        }
+    }
+
+    fn number(source: &str) -> TokenType {
+        lex(source).remove(0).token_type
+    }
+
+    fn number_error(source: &str) -> LexErrorKind {
+        let errors = File::new(source.as_bytes().to_vec()).tokenize().unwrap_err();
+        errors[0].kind.clone()
+    }
+
+    #[test]
+    fn radix_prefixes() {
+        assert_eq!(number("0xFF"), TokenType::Integer(255));
+        assert_eq!(number("0o17"), TokenType::Integer(15));
+        assert_eq!(number("0b1010"), TokenType::Integer(10));
+        assert_eq!(number("0"), TokenType::Integer(0));
+    }
+
+    #[test]
+    fn digit_separators() {
+        assert_eq!(number("1_000_000"), TokenType::Integer(1_000_000));
+        assert_eq!(number("0xdead_beef"), TokenType::Integer(0xdead_beef));
+        assert_eq!(number("1_000.000_1"), TokenType::Float(1000.0001));
+    }
+
+    #[test]
+    fn float_forms() {
+        assert_eq!(number("1.5e-3"), TokenType::Float(1.5e-3));
+        assert_eq!(number("2E+2"), TokenType::Float(200.0));
+        assert_eq!(number("3e2"), TokenType::Float(300.0));
+        assert_eq!(number(".5"), TokenType::Float(0.5));
+        assert_eq!(number("1."), TokenType::Float(1.0));
+    }
+
+    #[test]
+    fn invalid_numbers() {
+        assert_eq!(number_error("0b102"), LexErrorKind::InvalidInteger('2'));
+        assert_eq!(number_error("0x"), LexErrorKind::MissingDigits);
+        assert_eq!(number_error("1e"), LexErrorKind::MissingDigits);
+        assert_eq!(number_error("1.2.3"), LexErrorKind::TooManyDecimalPoints);
+        assert_eq!(number_error("1e5.3"), LexErrorKind::InvalidFloat('.'));
+    }
+
+    #[test]
+    fn overflowing_literals() {
+        assert_eq!(number("9223372036854775807"), TokenType::Integer(i64::MAX));
+        assert_eq!(number_error("9223372036854775808"), LexErrorKind::NumberOverflow);
+        assert_eq!(number_error("0x1_0000_0000_0000_0000"), LexErrorKind::NumberOverflow);
+        assert_eq!(number_error("1e400"), LexErrorKind::NumberOverflow);
+
+        let error = &File::new(b"x = 99999999999999999999".to_vec()).tokenize().unwrap_err()[0];
+        assert_eq!((error.span.start, error.span.end), (4, 24));
+    }
+
+    // source snippets paired with the token stream they should produce.
+    fn corpus() -> Vec<(&'static str, Vec<TokenType>)> {
+        use TokenType::*;
+        vec![
+            ("print(1)", vec![ident("print"), LeftParen, Integer(1), RightParen, Newline, Eof]),
+            ("x = 3;", vec![ident("x"), Assign, Integer(3), Semicolon, Newline, Eof]),
+            (
+                "[1,2]",
+                vec![LeftSquare, Integer(1), Comma, Integer(2), RightSquare, Newline, Eof],
+            ),
+            ("1+2", vec![Integer(1), Plus, Integer(2), Newline, Eof]),
+            ("1-2", vec![Integer(1), Minus, Integer(2), Newline, Eof]),
+            ("3*4/5", vec![Integer(3), Asterisk, Integer(4), Slash, Integer(5), Newline, Eof]),
+            ("1<2", vec![Integer(1), Less, Integer(2), Newline, Eof]),
+            ("1>=2", vec![Integer(1), GreaterEqual, Integer(2), Newline, Eof]),
+            ("1==2", vec![Integer(1), Equal, Integer(2), Newline, Eof]),
+            ("a+=1\n", vec![ident("a"), PlusAssign, Integer(1), Newline, Eof]),
+            ("1.5)", vec![Float(1.5), RightParen, Newline, Eof]),
+            ("{x: 2.5}", vec![LeftBrace, ident("x"), Colon, Float(2.5), RightBrace, Newline, Eof]),
+            ("0xFF,0b1", vec![Integer(255), Comma, Integer(1), Newline, Eof]),
+            ("1e3;", vec![Float(1000.0), Semicolon, Newline, Eof]),
+            ("1\t2", vec![Integer(1), Integer(2), Newline, Eof]),
+            ("1\r\n", vec![Integer(1), Newline, Eof]),
+            (
+                "f(1, .5)",
+                vec![ident("f"), LeftParen, Integer(1), Comma, Float(0.5), RightParen, Newline, Eof],
+            ),
+            ("7 # seven", vec![Integer(7), Newline, Eof]),
+            ("7/* seven */", vec![Integer(7), Newline, Eof]),
+            ("1&2|3", vec![Integer(1), BitAnd, Integer(2), BitOr, Integer(3), Newline, Eof]),
+            ("1^~2", vec![Integer(1), Caret, Tilde, Integer(2), Newline, Eof]),
+            ("1<<2>>3", vec![Integer(1), ShiftLeft, Integer(2), ShiftRight, Integer(3), Newline, Eof]),
+            ("a&&b||!c", vec![ident("a"), AndAnd, ident("b"), OrOr, Bang, ident("c"), Newline, Eof]),
+            ("a and b or not c", vec![ident("a"), And, ident("b"), Or, Not, ident("c"), Newline, Eof]),
+            ("1!=2", vec![Integer(1), NotEqual, Integer(2), Newline, Eof]),
+            ("7%2**3", vec![Integer(7), Percent, Integer(2), DoubleAsterisk, Integer(3), Newline, Eof]),
+            ("7//2/1", vec![Integer(7), DoubleSlash, Integer(2), Slash, Integer(1), Newline, Eof]),
+            ("a*=1", vec![ident("a"), AsteriskAssign, Integer(1), Newline, Eof]),
+            ("a/=1", vec![ident("a"), SlashAssign, Integer(1), Newline, Eof]),
+            ("a%=1", vec![ident("a"), PercentAssign, Integer(1), Newline, Eof]),
+            ("a**=1", vec![ident("a"), DoubleAsteriskAssign, Integer(1), Newline, Eof]),
+            ("a//=1", vec![ident("a"), DoubleSlashAssign, Integer(1), Newline, Eof]),
+            ("obj.field", vec![ident("obj"), Dot, ident("field"), Newline, Eof]),
+            ("1..10", vec![Integer(1), DotDot, Integer(10), Newline, Eof]),
+            ("1..=10", vec![Integer(1), DotDotEq, Integer(10), Newline, Eof]),
+            ("1.5..2", vec![Float(1.5), DotDot, Integer(2), Newline, Eof]),
+            ("0x1..a", vec![Integer(1), DotDot, ident("a"), Newline, Eof]),
+            ("a..b", vec![ident("a"), DotDot, ident("b"), Newline, Eof]),
+            ("a->b", vec![ident("a"), Arrow, ident("b"), Newline, Eof]),
+            ("a=>b", vec![ident("a"), FatArrow, ident("b"), Newline, Eof]),
+            ("a?", vec![ident("a"), Question, Newline, Eof]),
+            ("a::b", vec![ident("a"), DoubleColon, ident("b"), Newline, Eof]),
+            ("1<=2>=3", vec![Integer(1), LessEqual, Integer(2), GreaterEqual, Integer(3), Newline, Eof]),
+            ("1\"a\"", vec![Integer(1), StringLiteral("a".to_string()), Newline, Eof]),
+        ]
+    }
+
+    #[test]
+    fn token_stream_corpus() {
+        for (source, expected) in corpus() {
+            assert_eq!(types(source), expected, "{source:?}");
+        }
+    }
+
+    #[test]
+    fn letters_still_invalidate_numbers() {
+        assert_eq!(number_error("12ab"), LexErrorKind::InvalidInteger('a'));
+        assert_eq!(number_error("1.5x"), LexErrorKind::InvalidFloat('x'));
+    }
+
+    #[test]
+    fn unicode_identifiers() {
+        assert_eq!(types("সংখ্যা = 1")[0], ident("সংখ্যা"));
+        assert_eq!(types("_private")[0], ident("_private"));
+        assert_eq!(types("π2")[0], ident("π2"));
+        assert_eq!(types("x_1y")[0], ident("x_1y"));
+    }
+
+    #[test]
+    fn identifiers_are_nfc_normalized() {
+        // "é" precomposed and as e + combining acute accent.
+        assert_eq!(types("caf\u{e9}")[0], types("cafe\u{301}")[0]);
+    }
+
+    #[test]
+    fn non_identifier_characters() {
+        let errors = File::new("a ∑ b".as_bytes().to_vec()).tokenize().unwrap_err();
+        assert_eq!(errors[0].kind, LexErrorKind::UnknownCharacter('∑'));
+        assert_eq!((errors[0].span.start, errors[0].span.end), (2, 5));
+
+        let errors = File::new(b"a \xff b".to_vec()).tokenize().unwrap_err();
+        assert_eq!(errors[0].kind, LexErrorKind::InvalidUtf8);
+        assert_eq!((errors[0].span.start, errors[0].span.end), (2, 3));
+    }
+
+    #[test]
+    fn string_escapes() {
+        // source, decoded string, error and its byte span.
+        type Case = (&'static str, &'static str, Option<(LexErrorKind, usize, usize)>);
+        let replacement = "\u{FFFD}";
+        let cases: &[Case] = &[
+            (r#""\\""#, "\\", None),
+            (r#""a\"b""#, "a\"b", None),
+            (r#""\n\t\r\0\'""#, "\n\t\r\0'", None),
+            (r#""\x41""#, "A", None),
+            (r#""\x7F""#, "\x7F", None),
+            (r#""\x80""#, replacement, Some((LexErrorKind::InvalidHexEscape, 1, 5))),
+            (r#""\x4""#, replacement, Some((LexErrorKind::InvalidHexEscape, 1, 4))),
+            (r#""\u{1F600}""#, "😀", None),
+            (r#""\u{10FFFF}""#, "\u{10FFFF}", None),
+            (r#""\u{}""#, replacement, Some((LexErrorKind::InvalidUnicodeEscape, 1, 5))),
+            (r#""\u{D800}""#, replacement, Some((LexErrorKind::InvalidUnicodeEscape, 1, 9))),
+            (r#""\u{110000}""#, replacement, Some((LexErrorKind::InvalidUnicodeEscape, 1, 11))),
+            (r#""\u{1234567}""#, replacement, Some((LexErrorKind::InvalidUnicodeEscape, 1, 12))),
+            (r#""\u41""#, "\u{FFFD}41", Some((LexErrorKind::InvalidUnicodeEscape, 1, 3))),
+            (r#""\q""#, replacement, Some((LexErrorKind::UnknownEscape('q'), 1, 3))),
+        ];
+        for (source, value, error) in cases {
+            let (tokens, errors) = lex_with_errors(source);
+            assert_eq!(tokens[0], TokenType::StringLiteral(value.to_string()), "{source}");
+            let errors: Vec<_> =
+                errors.into_iter().map(|(kind, span)| (kind, span.start, span.end)).collect();
+            assert_eq!(errors, error.iter().cloned().collect::<Vec<_>>(), "{source}");
+        }
+
+        // an escaped newline still counts as a line, positions after it are right.
+        let (_, errors) = lex_with_errors("x = \"a\\\nb\"\n$");
+        assert_eq!(
+            errors,
+            vec![
+                (LexErrorKind::UnknownEscape('\n'), Span::new(6, 8, 1, 7)),
+                (LexErrorKind::UnknownCharacter('$'), Span::new(11, 12, 3, 1)),
+            ]
+        );
+
+        // a backslash right before the end of the file leaves the string open.
+        let (_, errors) = lex_with_errors("\"a\\");
+        assert_eq!(errors, vec![(LexErrorKind::UnterminatedString, Span::new(0, 3, 1, 1))]);
+    }
+
+    #[test]
+    fn lexer_is_lazy() {
+        let source = b"a b c $";
+        let mut lexer = Lexer::new(source);
+        assert_eq!(lexer.next().unwrap().token_type, ident("a"));
+        // the bad character at the end was not reached yet.
+        assert!(lexer.errors().is_empty());
+        assert_eq!(lexer.by_ref().count(), 2);
+        assert_eq!(lexer.errors().len(), 1);
+    }
+
+    #[test]
+    fn peek_nth_does_not_consume() {
+        let mut lexer = Lexer::new(b"x = 1");
+        assert_eq!(lexer.peek_nth(2).unwrap().token_type, TokenType::Integer(1));
+        assert_eq!(lexer.peek().unwrap().token_type, ident("x"));
+        assert!(lexer.peek_nth(10).is_none());
+        let types: Vec<TokenType> = lexer.map(|t| t.token_type).collect();
+        assert_eq!(
+            types,
+            vec![ident("x"), TokenType::Assign, TokenType::Integer(1), TokenType::Newline, TokenType::Eof]
+        );
+    }
+
+    #[test]
+    fn token_text_and_accessors() {
+        let mut file = File::new(b"name = \"hi\" + 12".to_vec());
+        assert!(file.tokens().is_empty());
+        file.tokenize().unwrap();
+
+        let texts: Vec<_> = file.tokens().iter().map(|t| t.text(&file).into_owned()).collect();
+        assert_eq!(texts, vec!["name", "=", "\"hi\"", "+", "12", "", ""]);
+
+        let inside = file.tokens_in(5, 12);
+        assert_eq!(inside.len(), 2);
+        assert_eq!(inside[0].token_type, TokenType::Assign);
+    }
+
+    #[test]
+    fn comments() {
+        let source = "# line\nx = 1 /* a /* nested */ b */\n";
+        assert_eq!(
+            types(source),
+            vec![ident("x"), TokenType::Assign, TokenType::Integer(1), TokenType::Newline, TokenType::Eof]
+        );
+
+        let mut file = File::new(source.as_bytes().to_vec()).keep_comments(true);
+        file.tokenize().unwrap();
+        let comments: Vec<_> = file
+            .tokens()
+            .iter()
+            .filter(|t| t.token_type == TokenType::Comment)
+            .map(|t| t.text(&file).into_owned())
+            .collect();
+        assert_eq!(comments, vec!["# line", "/* a /* nested */ b */"]);
+
+        let errors = File::new(b"/* /* */".to_vec()).tokenize().unwrap_err();
+        assert_eq!(errors[0].kind, LexErrorKind::UnterminatedComment);
+    }
+
+    #[test]
+    fn double_slash_is_not_a_comment() {
+        use TokenType::*;
+        assert_eq!(types("// x"), vec![DoubleSlash, ident("x"), Newline, Eof]);
+        assert_eq!(types("a // b # c"), vec![ident("a"), DoubleSlash, ident("b"), Newline, Eof]);
+    }
+
+    #[test]
+    fn block_comment_lines_keep_the_indentation() {
+        use TokenType::*;
+        let plain = vec![
+            ident("x"), Assign, Integer(1), Newline, ident("y"), Assign, Integer(2), Newline, Eof,
+        ];
+        assert_eq!(types("x = 1\n  /* note */\ny = 2\n"), plain);
+        assert_eq!(types("x = 1\n  /* a */ /* b */ # c\ny = 2\n"), plain);
+        assert_eq!(types("x = 1\n  /* a\nb */\ny = 2\n"), plain);
 
-        println!("{:#?}", self.tokens);
+        // code after the comment is indented as usual.
+        assert_eq!(
+            types("if a:\n  /* note */ b\nc\n"),
+            vec![
+                If, ident("a"), Colon, Newline, Indent, ident("b"), Newline, Dedent, ident("c"),
+                Newline, Eof,
+            ]
+        );
     }
 }
@@ -0,0 +1,16 @@
+//! The lang language as a library.
+//!
+//! `lexer` turns source into tokens, `parser` turns tokens into the syntax tree
+//! in `ast`, `span` maps byte offsets back to lines and columns and `dump`
+//! prints tokens for tooling. Later stages (interp) live next to them as they
+//! are added.
+
+pub mod ast;
+pub mod dump;
+pub mod lexer;
+pub mod parser;
+pub mod span;
+
+pub use lexer::{File, LexError, LexErrorKind, Lexer, Token, TokenType};
+pub use parser::{ParseError, ParseErrorKind, Parser};
+pub use span::{SourceMap, Span};
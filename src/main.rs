@@ -1,22 +1,298 @@
-#![allow(unused)]
-mod lexer;
-
-use std::{fs, process::exit};
-use lexer::File;
-
-fn readfile(filename: &str) -> Vec<u8> {
-    match fs::read(filename) {
-        Ok(content) => content,
-        Err(e) => {
-            println!("Cannot read '{}' : {}", filename, e.to_string());
-            exit(404);
+use std::io::{self, Read, Write};
+use std::process::ExitCode;
+use std::{env, fs};
+
+use lang::File;
+use lang::dump::{self, Format};
+use lang::parser::{self, ParseErrorKind};
+
+const USAGE: &str = "\
+usage: lang <command> [options] <file>...
+
+Commands:
+  run <file>       run a program (not supported yet)
+  lex <file>...    print the tokens of each file
+  parse <file>...  print the syntax tree of each file
+  check <file>...  report every lexical and syntax error without running
+
+Use - as the file to read from stdin.
+
+Options:
+  -h, --help       print this help
+  -V, --version    print the version
+  --format=<fmt>   token format for 'lex': table (default), json or debug.
+                   json is an array of objects with kind, lexeme, span
+                   (start and end byte offsets, end exclusive), line and
+                   column. line and column are 1 based.
+
+Exit status:
+  0  success
+  1  the input has errors
+  2  bad command line usage, or a file could not be read
+  3  the command is not supported yet";
+
+// the input has lexical or syntax errors.
+const EXIT_INPUT_ERRORS: u8 = 1;
+// bad command line usage or unreadable input.
+const EXIT_USAGE: u8 = 2;
+// the command exists but isn't implemented, like 'run'.
+const EXIT_UNSUPPORTED: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Run,
+    Lex,
+    Parse,
+    Check,
+}
+
+impl Command {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "run" => Some(Command::Run),
+            "lex" => Some(Command::Lex),
+            "parse" => Some(Command::Parse),
+            "check" => Some(Command::Check),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Command::Run => "run",
+            Command::Lex => "lex",
+            Command::Parse => "parse",
+            Command::Check => "check",
         }
     }
 }
 
+// What the command line asks for.
+#[derive(Debug, PartialEq)]
+enum Action {
+    Help,
+    Version,
+    Execute(Options),
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    command: Command,
+    files: Vec<String>,
+    format: Format,
+}
 
-fn main() {
-    let filename = "./src/test.txt";
-    let mut file = File::new(readfile(filename));
-    file.tokenize();
+// Parses the arguments after the program name. Errors are usage errors, the
+// message is printed as is.
+fn parse_args(args: &[String]) -> Result<Action, String> {
+    let mut command = None;
+    let mut files = vec![];
+    let mut format = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Action::Help),
+            "-V" | "--version" => return Ok(Action::Version),
+            "--format" => match args.next() {
+                Some(name) => format = Some(name.as_str()),
+                None => return Err("'--format' needs a value".to_string()),
+            },
+            option if option.starts_with("--format=") => {
+                format = Some(&option["--format=".len()..]);
+            }
+            // - is stdin, not an option.
+            option if option.starts_with('-') && option != "-" => {
+                return Err(format!("unknown option '{}'", option));
+            }
+            name if command.is_none() => match Command::from_name(name) {
+                Some(c) => command = Some(c),
+                None => return Err(format!("unknown command '{}'", name)),
+            },
+            file => files.push(file.to_string()),
+        }
+    }
+
+    let Some(command) = command else {
+        return Err("no command given".to_string());
+    };
+    if files.is_empty() {
+        return Err("no input file given".to_string());
+    }
+    if command == Command::Run && files.len() > 1 {
+        return Err("'run' takes a single file".to_string());
+    }
+    if format.is_some() && command != Command::Lex {
+        return Err("'--format' only applies to 'lex'".to_string());
+    }
+    let format = format.map(str::parse).transpose()?.unwrap_or(Format::Table);
+    Ok(Action::Execute(Options {
+        command,
+        files,
+        format,
+    }))
+}
+
+// A source file read from disk or stdin, with the name used in messages.
+struct Source {
+    name: String,
+    file: File,
+}
+
+fn usage_error(message: &str) -> ExitCode {
+    eprintln!("lang: {}", message);
+    eprintln!("Try 'lang --help' for more information.");
+    ExitCode::from(EXIT_USAGE)
+}
+
+fn readfile(filename: &str) -> io::Result<Source> {
+    let (name, content) = if filename == "-" {
+        let mut content = vec![];
+        io::stdin().read_to_end(&mut content)?;
+        ("<stdin>".to_string(), content)
+    } else {
+        (filename.to_string(), fs::read(filename)?)
+    };
+    Ok(Source {
+        name,
+        file: File::new(content),
+    })
+}
+
+// Lex `source`, printing its errors and its tokens in `format` if given.
+// Returns true if there were no errors.
+fn lex(source: &mut Source, format: Option<Format>) -> bool {
+    let (_, errors) = source.file.tokenize_with_recovery();
+    if let Some(format) = format {
+        // a closed pipe (like `lang lex f | head`) is not an error worth a panic.
+        let _ = writeln!(io::stdout(), "{}", dump::dump(&source.file, format));
+    }
+    for error in &errors {
+        eprintln!("{}: Lexer: {}", source.name, error);
+    }
+    errors.is_empty()
+}
+
+// Parse `source`, printing its lexical and syntax errors and, if `print` is
+// set, the syntax tree. The tree is printed even with errors, the parts that
+// didn't parse show up as (error). Returns true if there were no errors.
+fn parse(source: &Source, print: bool) -> bool {
+    let (program, errors) = parser::parse_with_recovery(&source.file);
+    if print {
+        let _ = writeln!(io::stdout(), "{}", program);
+    }
+    for error in &errors {
+        let stage = match error.kind {
+            ParseErrorKind::Lex(_) => "Lexer",
+            _ => "Parser",
+        };
+        eprintln!("{}: {}: {}", source.name, stage, error);
+    }
+    errors.is_empty()
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(Action::Help) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Ok(Action::Version) => {
+            println!("lang {}", env!("CARGO_PKG_VERSION"));
+            return ExitCode::SUCCESS;
+        }
+        Ok(Action::Execute(options)) => options,
+        Err(message) => return usage_error(&message),
+    };
+
+    // like cat or grep, a file that can't be read is reported and the other
+    // files are still handled.
+    let mut ok = true;
+    let mut unreadable = false;
+    for filename in &options.files {
+        let mut source = match readfile(filename) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("lang: cannot read '{}': {}", filename, e);
+                unreadable = true;
+                continue;
+            }
+        };
+
+        ok &= match options.command {
+            Command::Lex => lex(&mut source, Some(options.format)),
+            // 'run' checks the program, running it isn't supported yet.
+            Command::Check | Command::Run => parse(&source, false),
+            Command::Parse => parse(&source, true),
+        };
+    }
+
+    if unreadable {
+        return ExitCode::from(EXIT_USAGE);
+    }
+    if !ok {
+        return ExitCode::from(EXIT_INPUT_ERRORS);
+    }
+    if options.command == Command::Run {
+        eprintln!("lang: '{}' is not supported yet", options.command.name());
+        return ExitCode::from(EXIT_UNSUPPORTED);
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Result<Action, String> {
+        let args: Vec<String> = line.split_whitespace().map(String::from).collect();
+        parse_args(&args)
+    }
+
+    fn options(command: Command, files: &[&str], format: Format) -> Result<Action, String> {
+        Ok(Action::Execute(Options {
+            command,
+            files: files.iter().map(|f| f.to_string()).collect(),
+            format,
+        }))
+    }
+
+    #[test]
+    fn commands_and_files() {
+        assert_eq!(args("lex a b"), options(Command::Lex, &["a", "b"], Format::Table));
+        assert_eq!(args("parse a"), options(Command::Parse, &["a"], Format::Table));
+        assert_eq!(args("check a b"), options(Command::Check, &["a", "b"], Format::Table));
+        assert_eq!(args("run a"), options(Command::Run, &["a"], Format::Table));
+        // - is stdin, a file like any other.
+        assert_eq!(args("lex -"), options(Command::Lex, &["-"], Format::Table));
+    }
+
+    #[test]
+    fn help_and_version() {
+        assert_eq!(args("-h"), Ok(Action::Help));
+        assert_eq!(args("lex a --help"), Ok(Action::Help));
+        assert_eq!(args("-V"), Ok(Action::Version));
+        assert_eq!(args("--version lex"), Ok(Action::Version));
+    }
+
+    #[test]
+    fn format_option() {
+        assert_eq!(args("lex --format json a"), options(Command::Lex, &["a"], Format::Json));
+        assert_eq!(args("lex a --format=debug"), options(Command::Lex, &["a"], Format::Debug));
+        assert_eq!(args("lex a --format"), Err("'--format' needs a value".to_string()));
+        assert!(args("lex a --format=xml").is_err());
+        assert_eq!(
+            args("parse a --format=json"),
+            Err("'--format' only applies to 'lex'".to_string())
+        );
+    }
+
+    #[test]
+    fn usage_errors() {
+        assert_eq!(args(""), Err("no command given".to_string()));
+        assert_eq!(args("lex"), Err("no input file given".to_string()));
+        assert_eq!(args("build a"), Err("unknown command 'build'".to_string()));
+        assert_eq!(args("lex -x a"), Err("unknown option '-x'".to_string()));
+        assert_eq!(args("run a b"), Err("'run' takes a single file".to_string()));
+    }
 }
@@ -0,0 +1,1326 @@
+//! Recursive descent parser from tokens to the `ast`.
+//!
+//! The parser pulls tokens from a `Lexer` on demand, so lexing and parsing
+//! happen in one pass.
+//!
+//! # Statements
+//!
+//! A newline ends a statement, `;` is only needed to put several statements on
+//! one line. A newline doesn't end the statement when the line obviously goes
+//! on: inside `(` and `[`, or after a binary operator, assignment, `,` or `.`
+//! at the end of the line. The lexer leaves the `Newline` token out there.
+//!
+//! ```text
+//! total = price *        items = [
+//!     count                  1, 2,
+//! a = 1; b = 2           ]
+//! ```
+//!
+//! A statement also ends at the `}` or the end of its block. Stray `;` are empty
+//! statements and ignored.
+//!
+//! # Blocks
+//!
+//! Bodies of `if`, `else`, `while`, `for` and `function` are written either in
+//! braces or as a `:` followed by an indented block. Both give the same `Block`
+//! in the tree and can be mixed in a file:
+//!
+//! ```text
+//! if a == 1 {            if a == 1:
+//!     print(a)               print(a)
+//! } else {               else:
+//!     print(b)               print(b)
+//! }
+//! ```
+//!
+//! Inside braces indentation doesn't matter, a one line `if a { b }` is fine.
+//! A file can enforce one style with a pragma in the comments at its top,
+//! before any code. Blocks of the other style are then reported as errors:
+//!
+//! ```text
+//! # lang: blocks = braces
+//! # lang: blocks = indent
+//! ```
+//!
+//! Errors don't stop the parser. A statement that doesn't parse becomes a
+//! `StmtKind::Error` and parsing picks up again after the next `;` or newline,
+//! or at the next `}`, end of block or statement keyword (`if`, `while`, `for`,
+//! `function`, `return`). A bad condition in an `if`, `while` or `for` header
+//! becomes an `ExprKind::Error` so the body still gets parsed, and so do tokens
+//! the lexer couldn't make sense of. Every error is collected.
+//!
+//! ```text
+//! program    = statement*
+//! statement  = if | while | for | function | return | expression
+//! if         = "if" expression block ("else" (if | block))?
+//! while      = "while" expression block
+//! for        = "for" IDENTIFIER "in" expression block
+//! function   = "function" IDENTIFIER "(" (IDENTIFIER ","?)* ")" block
+//! return     = "return" expression?
+//! block      = "{" statement* "}" | ":" NEWLINE INDENT statement+ DEDENT
+//! expression = prefix* primary (postfix | infix expression)*
+//! primary    = NUMBER | STRING | "true" | "false" | "nil" | IDENTIFIER | "(" expression ")"
+//! ```
+//!
+//! # Operator precedence
+//!
+//! Expressions are parsed with a Pratt parser driven by `infix_precedence`.
+//! Operators from the tightest to the loosest binding:
+//!
+//! | Precedence | Operators                                | Associativity |
+//! |------------|------------------------------------------|---------------|
+//! | Postfix    | `f(x)` `a[i]` `a.b` `a?`                 | left          |
+//! | Power      | `**`                                     | right         |
+//! | Prefix     | `-a` `!a` `not a` `~a`                   | prefix        |
+//! | Factor     | `*` `/` `//` `%`                         | left          |
+//! | Term       | `+` `-`                                  | left          |
+//! | Shift      | `<<` `>>`                                | left          |
+//! | BitAnd     | `&`                                      | left          |
+//! | BitXor     | `^`                                      | left          |
+//! | BitOr      | `\|`                                     | left          |
+//! | Comparison | `<` `<=` `>` `>=`                        | left          |
+//! | Equality   | `==` `!=`                                | left          |
+//! | And        | `&&` `and`                               | left          |
+//! | Or         | `\|\|` `or`                              | left          |
+//! | Range      | `..` `..=`                               | none          |
+//! | Assignment | `=` `+=` `-=` `*=` `/=` `%=` `**=` `//=` | right         |
+//!
+//! Some consequences worth knowing:
+//!
+//! - bitwise operators bind tighter than comparisons, unlike C:
+//!   `a == 1 & b < 2` is `a == ((1 & b) < 2)`.
+//! - `**` binds tighter than a prefix on its left but takes one on its right:
+//!   `-2 ** 2` is `-(2 ** 2)` and `2 ** -1` is `2 ** (-1)`.
+//! - `not` is the same operator as `!`, so `not a == b` is `(not a) == b`.
+//! - ranges don't chain, `a..b..c` is an error.
+//! - only names, `a[i]` and `a.b` can be assigned to.
+
+use std::fmt;
+
+use crate::ast::{
+    AssignOp, BinaryOp, Block, Expr, ExprKind, Ident, Program, Stmt, StmtKind, UnaryOp,
+};
+use crate::lexer::{File, LexErrorKind, Lexer, Token, TokenType};
+use crate::span::{SourceMap, Span};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    // an error from the lexer, the bad input is an Error node in the tree.
+    Lex(LexErrorKind),
+    // `expected` describes what the grammar wanted, `found` the token instead.
+    Expected {
+        expected: &'static str,
+        found: String,
+    },
+    InvalidAssignmentTarget,
+    // a second non associative operator, like the second `..` in a..b..c.
+    NonAssociative(String),
+    // a `# lang:` pragma setting that isn't known.
+    InvalidPragma(String),
+    // a block in the other style than the one the file's pragma asks for.
+    WrongBlockStyle(BlockStyle),
+}
+
+/// A syntax error. `span` covers the offending token or expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::Lex(kind) => write!(f, "{}", kind),
+            ParseErrorKind::Expected { expected, found } => {
+                write!(f, "expected {}, found {}", expected, found)
+            }
+            ParseErrorKind::InvalidAssignmentTarget => {
+                write!(f, "can only assign to a name, an index or a field")
+            }
+            ParseErrorKind::NonAssociative(operator) => {
+                write!(f, "'{}' can't be chained, use parentheses", operator)
+            }
+            ParseErrorKind::InvalidPragma(setting) => write!(
+                f,
+                "unknown pragma setting '{}', expected 'blocks = braces' or 'blocks = indent'",
+                setting
+            ),
+            ParseErrorKind::WrongBlockStyle(BlockStyle::Braces) => {
+                write!(f, "this file uses {{ }} blocks, as set by its 'lang: blocks' pragma")
+            }
+            ParseErrorKind::WrongBlockStyle(BlockStyle::Indent) => write!(
+                f,
+                "this file uses ':' and indented blocks, as set by its 'lang: blocks' pragma"
+            ),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at line {}, column {}", self.kind, self.span.line, self.span.col)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// How tightly an infix or postfix operator binds, from the loosest to the
+/// tightest. See the table in the module docs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Precedence {
+    // not an operator, everything binds tighter.
+    Lowest,
+    Assignment,
+    Range,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shift,
+    Term,
+    Factor,
+    Prefix,
+    Power,
+    Postfix,
+}
+
+impl Precedence {
+    // the next looser level, for right associative operators.
+    fn below(self) -> Precedence {
+        match self {
+            Precedence::Lowest | Precedence::Assignment => Precedence::Lowest,
+            Precedence::Range => Precedence::Assignment,
+            Precedence::Or => Precedence::Range,
+            Precedence::And => Precedence::Or,
+            Precedence::Equality => Precedence::And,
+            Precedence::Comparison => Precedence::Equality,
+            Precedence::BitOr => Precedence::Comparison,
+            Precedence::BitXor => Precedence::BitOr,
+            Precedence::BitAnd => Precedence::BitXor,
+            Precedence::Shift => Precedence::BitAnd,
+            Precedence::Term => Precedence::Shift,
+            Precedence::Factor => Precedence::Term,
+            Precedence::Prefix => Precedence::Factor,
+            Precedence::Power => Precedence::Prefix,
+            Precedence::Postfix => Precedence::Power,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    Left,
+    Right,
+    None,
+}
+
+/// Precedence and associativity of `token_type` after an operand, None if it
+/// is not an infix or postfix operator.
+pub fn infix_precedence(token_type: &TokenType) -> Option<(Precedence, Associativity)> {
+    let precedence = match token_type {
+        TokenType::Assign
+        | TokenType::PlusAssign
+        | TokenType::MinusAssign
+        | TokenType::AsteriskAssign
+        | TokenType::SlashAssign
+        | TokenType::PercentAssign
+        | TokenType::DoubleAsteriskAssign
+        | TokenType::DoubleSlashAssign => return Some((Precedence::Assignment, Associativity::Right)),
+        TokenType::DotDot | TokenType::DotDotEq => {
+            return Some((Precedence::Range, Associativity::None));
+        }
+        TokenType::DoubleAsterisk => return Some((Precedence::Power, Associativity::Right)),
+        TokenType::OrOr | TokenType::Or => Precedence::Or,
+        TokenType::AndAnd | TokenType::And => Precedence::And,
+        TokenType::Equal | TokenType::NotEqual => Precedence::Equality,
+        TokenType::Less | TokenType::LessEqual | TokenType::Greater | TokenType::GreaterEqual => {
+            Precedence::Comparison
+        }
+        TokenType::BitOr => Precedence::BitOr,
+        TokenType::Caret => Precedence::BitXor,
+        TokenType::BitAnd => Precedence::BitAnd,
+        TokenType::ShiftLeft | TokenType::ShiftRight => Precedence::Shift,
+        TokenType::Plus | TokenType::Minus => Precedence::Term,
+        TokenType::Asterisk | TokenType::Slash | TokenType::DoubleSlash | TokenType::Percent => {
+            Precedence::Factor
+        }
+        TokenType::LeftParen | TokenType::LeftSquare | TokenType::Dot | TokenType::Question => {
+            Precedence::Postfix
+        }
+        _ => return None,
+    };
+    Some((precedence, Associativity::Left))
+}
+
+fn binary_op(token_type: &TokenType) -> Option<BinaryOp> {
+    let op = match token_type {
+        TokenType::OrOr | TokenType::Or => BinaryOp::Or,
+        TokenType::AndAnd | TokenType::And => BinaryOp::And,
+        TokenType::Equal => BinaryOp::Equal,
+        TokenType::NotEqual => BinaryOp::NotEqual,
+        TokenType::Less => BinaryOp::Less,
+        TokenType::LessEqual => BinaryOp::LessEqual,
+        TokenType::Greater => BinaryOp::Greater,
+        TokenType::GreaterEqual => BinaryOp::GreaterEqual,
+        TokenType::BitOr => BinaryOp::BitOr,
+        TokenType::Caret => BinaryOp::BitXor,
+        TokenType::BitAnd => BinaryOp::BitAnd,
+        TokenType::ShiftLeft => BinaryOp::ShiftLeft,
+        TokenType::ShiftRight => BinaryOp::ShiftRight,
+        TokenType::Plus => BinaryOp::Add,
+        TokenType::Minus => BinaryOp::Sub,
+        TokenType::Asterisk => BinaryOp::Mul,
+        TokenType::Slash => BinaryOp::Div,
+        TokenType::Percent => BinaryOp::Rem,
+        TokenType::DoubleSlash => BinaryOp::IntDiv,
+        TokenType::DoubleAsterisk => BinaryOp::Pow,
+        TokenType::DotDot => BinaryOp::Range,
+        TokenType::DotDotEq => BinaryOp::RangeInclusive,
+        _ => return None,
+    };
+    Some(op)
+}
+
+/// How the bodies of `if`, `while`, `for` and `function` are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockStyle {
+    /// `{ ... }`
+    Braces,
+    /// `:` and an indented block.
+    Indent,
+}
+
+// Reads the `# lang: blocks = braces|indent` pragma from the comment lines at
+// the top of `raw`. Returns the block style it asks for and the errors for any
+// settings that aren't known.
+fn pragma(raw: &[u8]) -> (Option<BlockStyle>, Vec<ParseError>) {
+    let mut style = None;
+    let mut errors = vec![];
+    let mut line_start = 0;
+    for line in raw.split(|b| *b == b'\n') {
+        let start = line_start;
+        line_start += line.len() + 1;
+
+        let text = String::from_utf8_lossy(line);
+        let text = text.trim();
+        if text.is_empty() {
+            continue;
+        }
+        // the pragma has to come before any code.
+        let Some(comment) = text.strip_prefix('#') else {
+            break;
+        };
+        let Some(settings) = comment.trim().strip_prefix("lang:") else {
+            continue;
+        };
+        for setting in settings.split(',') {
+            let setting = setting.trim();
+            let value = setting
+                .split_once('=')
+                .filter(|(key, _)| key.trim() == "blocks")
+                .map(|(_, value)| value.trim());
+            match value {
+                Some("braces") => style = Some(BlockStyle::Braces),
+                Some("indent") => style = Some(BlockStyle::Indent),
+                _ => errors.push(ParseError {
+                    kind: ParseErrorKind::InvalidPragma(setting.to_string()),
+                    span: SourceMap::new(raw).span(start, start + line.len()),
+                }),
+            }
+        }
+    }
+    (style, errors)
+}
+
+/// Parse `file`, reporting every lexical and syntax error in it.
+pub fn parse(file: &File) -> Result<Program, Vec<ParseError>> {
+    let (program, errors) = parse_with_recovery(file);
+    if errors.is_empty() {
+        Ok(program)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Parse `file` into a tree even if it has errors. The parts that didn't parse
+/// are `Error` nodes, the errors are sorted by position.
+pub fn parse_with_recovery(file: &File) -> (Program, Vec<ParseError>) {
+    Parser::new(file.source()).parse()
+}
+
+pub struct Parser<'a> {
+    raw: &'a [u8],
+    lexer: Lexer<'a>,
+    errors: Vec<ParseError>,
+    // the block style the file's pragma enforces, if it has one.
+    block_style: Option<BlockStyle>,
+    // handed out by peek once the lexer has stopped.
+    eof: Token,
+    // span of the last token taken that wasn't layout, nodes end there.
+    previous: Span,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(raw: &'a [u8]) -> Self {
+        let (block_style, errors) = pragma(raw);
+        Self {
+            raw,
+            lexer: Lexer::new(raw).recovery(true),
+            errors,
+            block_style,
+            eof: Token {
+                token_type: TokenType::Eof,
+                span: Span::default(),
+            },
+            previous: Span::default(),
+        }
+    }
+
+    /// Parse the whole source into a `Program`, along with the lexical and
+    /// syntax errors in it sorted by position.
+    pub fn parse(&mut self) -> (Program, Vec<ParseError>) {
+        let mut statements = vec![];
+        self.statements(&mut statements);
+        let program = Program {
+            statements,
+            span: Span::new(0, self.raw.len(), 1, 1),
+        };
+
+        let mut errors: Vec<ParseError> = self
+            .lexer
+            .errors()
+            .iter()
+            .map(|error| ParseError {
+                kind: ParseErrorKind::Lex(error.kind.clone()),
+                span: error.span,
+            })
+            .collect();
+        errors.append(&mut self.errors);
+        // stable, so a lexer error stays ahead of a syntax error at the same place.
+        errors.sort_by_key(|error| error.span.start);
+        (program, errors)
+    }
+
+    // Record `error`, unless it is about a token the lexer already reported.
+    fn report(&mut self, error: ParseError) {
+        if matches!(error.kind, ParseErrorKind::Expected { .. }) && self.at(&TokenType::Error) {
+            return;
+        }
+        self.errors.push(error);
+    }
+
+    // ---- tokens ----
+
+    fn peek(&mut self) -> &Token {
+        match self.lexer.peek() {
+            Some(token) => token,
+            None => &self.eof,
+        }
+    }
+
+    fn at(&mut self, token_type: &TokenType) -> bool {
+        self.peek().token_type == *token_type
+    }
+
+    // Take the next token. Layout tokens don't move `previous`.
+    fn advance(&mut self) -> Token {
+        let token = match self.lexer.next() {
+            Some(token) => token,
+            None => self.eof.clone(),
+        };
+        if !matches!(
+            token.token_type,
+            TokenType::Newline | TokenType::Indent | TokenType::Dedent | TokenType::Eof
+        ) {
+            self.previous = token.span;
+        }
+        token
+    }
+
+    // Take the next token if it is `token_type`.
+    fn eat(&mut self, token_type: &TokenType) -> bool {
+        if self.at(token_type) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token_type: &TokenType, expected: &'static str) -> Result<Token, ParseError> {
+        if self.at(token_type) {
+            Ok(self.advance())
+        } else {
+            Err(self.unexpected(expected))
+        }
+    }
+
+    // error for the next token, which isn't what the grammar `expected`.
+    fn unexpected(&mut self, expected: &'static str) -> ParseError {
+        let token = self.peek().clone();
+        let found = match token.token_type {
+            TokenType::Eof => "end of file".to_string(),
+            TokenType::Newline => "end of line".to_string(),
+            TokenType::Indent => "indentation".to_string(),
+            TokenType::Dedent => "end of block".to_string(),
+            _ => format!(
+                "'{}'",
+                String::from_utf8_lossy(&self.raw[token.span.start..token.span.end])
+            ),
+        };
+        ParseError {
+            kind: ParseErrorKind::Expected { expected, found },
+            span: token.span,
+        }
+    }
+
+    fn ident(&mut self, expected: &'static str) -> Result<Ident, ParseError> {
+        let token = self.peek().clone();
+        match token.token_type {
+            TokenType::Identifier(name) => {
+                self.advance();
+                Ok(Ident {
+                    // identifiers are always valid utf-8.
+                    name: String::from_utf8_lossy(&name).into_owned(),
+                    span: token.span,
+                })
+            }
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    // ---- statements ----
+
+    // Statements up to the end of the block or the file. Indentation that
+    // doesn't open a block is an error, but its statements are kept.
+    fn statements(&mut self, statements: &mut Vec<Stmt>) {
+        while !self.at(&TokenType::Dedent) && !self.at(&TokenType::Eof) {
+            if self.eat(&TokenType::Semicolon) {
+                self.eat(&TokenType::Newline);
+            } else if self.at(&TokenType::Indent) {
+                let error = self.unexpected("a statement");
+                self.report(error);
+                self.advance();
+                self.statements(statements);
+                self.eat(&TokenType::Dedent);
+            } else {
+                statements.push(self.statement());
+            }
+        }
+    }
+
+    // A statement, or an Error statement if it doesn't parse. The error is
+    // recorded and parsing goes on with the next statement.
+    fn statement(&mut self) -> Stmt {
+        let start = self.peek().span;
+        match self.try_statement() {
+            Ok(statement) => statement,
+            Err(error) => {
+                self.report(error);
+                self.synchronize(start.start);
+                // nothing but layout may have been skipped.
+                let span = if self.previous.start >= start.start {
+                    start.to(self.previous)
+                } else {
+                    start
+                };
+                Stmt {
+                    kind: StmtKind::Error,
+                    span,
+                }
+            }
+        }
+    }
+
+    // Skip the rest of a broken statement: up to and including the next `;` or
+    // newline, or up to a `}`, the end of the block or a statement keyword.
+    // A statement that didn't get past its first token loses it, so the parser
+    // can't get stuck on it.
+    fn synchronize(&mut self, start: usize) {
+        if self.peek().span.start == start && !self.at(&TokenType::Eof) {
+            self.advance();
+        }
+        loop {
+            match self.peek().token_type {
+                TokenType::Semicolon => {
+                    self.advance();
+                    self.eat(&TokenType::Newline);
+                    return;
+                }
+                TokenType::Newline => {
+                    self.advance();
+                    // the body of a broken if/while/... header. errors in it are
+                    // still reported, but it has no statement to belong to.
+                    if self.eat(&TokenType::Indent) {
+                        let mut body = vec![];
+                        self.statements(&mut body);
+                        self.eat(&TokenType::Dedent);
+                    }
+                    return;
+                }
+                TokenType::Dedent
+                | TokenType::Eof
+                | TokenType::RightBrace
+                | TokenType::If
+                | TokenType::While
+                | TokenType::For
+                | TokenType::Function
+                | TokenType::Return => return,
+                _ => {
+                    self.advance();
+                }
+            }
+        }
+    }
+
+    // An expression in an if/while/for header. If it doesn't parse, the error is
+    // recorded and the header goes on at its `:` or `{` with an Error expression.
+    fn header_expression(&mut self) -> Expr {
+        let start = self.peek().span;
+        match self.expression() {
+            Ok(expr) => expr,
+            Err(error) => {
+                self.report(error);
+                while !matches!(
+                    self.peek().token_type,
+                    TokenType::Colon
+                        | TokenType::LeftBrace
+                        | TokenType::Newline
+                        | TokenType::Dedent
+                        | TokenType::Eof
+                ) {
+                    self.advance();
+                }
+                let span = if self.previous.start >= start.start {
+                    start.to(self.previous)
+                } else {
+                    Span::new(start.start, start.start, start.line, start.col)
+                };
+                Expr {
+                    kind: ExprKind::Error,
+                    span,
+                }
+            }
+        }
+    }
+
+    fn try_statement(&mut self) -> Result<Stmt, ParseError> {
+        match self.peek().token_type {
+            TokenType::If => self.if_statement(),
+            TokenType::While => self.while_statement(),
+            TokenType::For => self.for_statement(),
+            TokenType::Function => self.function(),
+            TokenType::Return => {
+                let start = self.advance().span;
+                let value = if self.at_statement_end() {
+                    None
+                } else {
+                    Some(self.expression()?)
+                };
+                let span = start.to(self.previous);
+                self.end_statement()?;
+                Ok(Stmt {
+                    kind: StmtKind::Return(value),
+                    span,
+                })
+            }
+            _ => {
+                let expr = self.expression()?;
+                let span = expr.span;
+                self.end_statement()?;
+                Ok(Stmt {
+                    kind: StmtKind::Expr(expr),
+                    span,
+                })
+            }
+        }
+    }
+
+    fn at_statement_end(&mut self) -> bool {
+        matches!(
+            self.peek().token_type,
+            TokenType::Newline
+                | TokenType::Semicolon
+                | TokenType::Dedent
+                | TokenType::RightBrace
+                | TokenType::Eof
+        )
+    }
+
+    // Simple statements end with `;`, a newline, or the end of the block/file.
+    fn end_statement(&mut self) -> Result<(), ParseError> {
+        if self.eat(&TokenType::Semicolon) {
+            self.eat(&TokenType::Newline);
+            return Ok(());
+        }
+        if self.eat(&TokenType::Newline) || self.at_statement_end() {
+            return Ok(());
+        }
+        Err(self.unexpected("end of statement"))
+    }
+
+    fn if_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.advance().span;
+        let condition = self.header_expression();
+        let then_block = self.block()?;
+
+        let else_block = if self.eat(&TokenType::Else) {
+            if self.at(&TokenType::If) {
+                let inner = self.if_statement()?;
+                Some(Block {
+                    span: inner.span,
+                    statements: vec![inner],
+                })
+            } else {
+                Some(self.block()?)
+            }
+        } else {
+            None
+        };
+
+        Ok(Stmt {
+            kind: StmtKind::If {
+                condition,
+                then_block,
+                else_block,
+            },
+            span: start.to(self.previous),
+        })
+    }
+
+    fn while_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.advance().span;
+        let condition = self.header_expression();
+        let body = self.block()?;
+        Ok(Stmt {
+            kind: StmtKind::While { condition, body },
+            span: start.to(self.previous),
+        })
+    }
+
+    fn for_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.advance().span;
+        let variable = self.ident("a loop variable")?;
+        self.expect(&TokenType::In, "'in'")?;
+        let iterable = self.header_expression();
+        let body = self.block()?;
+        Ok(Stmt {
+            kind: StmtKind::For {
+                variable,
+                iterable,
+                body,
+            },
+            span: start.to(self.previous),
+        })
+    }
+
+    fn function(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.advance().span;
+        let name = self.ident("a function name")?;
+        self.expect(&TokenType::LeftParen, "'('")?;
+        let mut params = vec![];
+        while !self.at(&TokenType::RightParen) {
+            params.push(self.ident("a parameter name")?);
+            if !self.eat(&TokenType::Comma) {
+                break;
+            }
+        }
+        self.expect(&TokenType::RightParen, "')'")?;
+        let body = self.block()?;
+        Ok(Stmt {
+            kind: StmtKind::Function { name, params, body },
+            span: start.to(self.previous),
+        })
+    }
+
+    // A body in either style. A style the pragma doesn't allow is reported,
+    // but the block is still parsed.
+    fn block(&mut self) -> Result<Block, ParseError> {
+        let style = match self.peek().token_type {
+            TokenType::LeftBrace => BlockStyle::Braces,
+            TokenType::Colon => BlockStyle::Indent,
+            _ => return Err(self.unexpected("'{' or ':'")),
+        };
+        if let Some(required) = self.block_style
+            && required != style
+        {
+            let error = ParseError {
+                kind: ParseErrorKind::WrongBlockStyle(required),
+                span: self.peek().span,
+            };
+            self.report(error);
+        }
+        match style {
+            BlockStyle::Braces => self.brace_block(),
+            BlockStyle::Indent => self.indented_block(),
+        }
+    }
+
+    // `{`, statements and `}`. Layout tokens inside don't matter, the lexer
+    // closes the indentation opened inside before the `}`.
+    fn brace_block(&mut self) -> Result<Block, ParseError> {
+        let open = self.advance().span;
+        let mut statements = vec![];
+        loop {
+            match self.peek().token_type {
+                // layout and empty statements.
+                TokenType::Newline
+                | TokenType::Indent
+                | TokenType::Dedent
+                | TokenType::Semicolon => {
+                    self.advance();
+                }
+                TokenType::RightBrace | TokenType::Eof => break,
+                _ => statements.push(self.statement()),
+            }
+        }
+        let close = self.expect(&TokenType::RightBrace, "'}'")?.span;
+        // the } ends the statement the block belongs to, like a newline would.
+        if !self.eat(&TokenType::Semicolon) {
+            self.eat(&TokenType::Newline);
+        }
+        Ok(Block {
+            statements,
+            span: open.to(close),
+        })
+    }
+
+    // `:` then an indented block of statements on the following lines.
+    fn indented_block(&mut self) -> Result<Block, ParseError> {
+        self.expect(&TokenType::Colon, "':'")?;
+        self.expect(&TokenType::Newline, "a new line after ':'")?;
+        let indent = self.expect(&TokenType::Indent, "an indented block")?;
+        let mut statements = vec![];
+        self.statements(&mut statements);
+        self.eat(&TokenType::Dedent);
+
+        let span = match statements.first() {
+            Some(first) => first.span.to(self.previous),
+            None => indent.span,
+        };
+        Ok(Block { statements, span })
+    }
+
+    // ---- expressions ----
+
+    fn expression(&mut self) -> Result<Expr, ParseError> {
+        self.expression_above(Precedence::Lowest)
+    }
+
+    // Pratt loop: parses an expression whose infix and postfix operators all
+    // bind tighter than `min`. Operators at `min` or below are left for the caller.
+    fn expression_above(&mut self, min: Precedence) -> Result<Expr, ParseError> {
+        let mut left = self.prefix()?;
+        while let Some((precedence, associativity)) = infix_precedence(&self.peek().token_type) {
+            if precedence <= min {
+                break;
+            }
+            left = match precedence {
+                Precedence::Postfix => self.postfix(left)?,
+                Precedence::Assignment => self.assignment(left)?,
+                _ => self.binary(left, precedence, associativity)?,
+            };
+
+            // a..b..c has no meaning, parentheses have to say what is meant.
+            if associativity == Associativity::None
+                && infix_precedence(&self.peek().token_type).map(|(p, _)| p) == Some(precedence)
+            {
+                let token = self.peek().clone();
+                return Err(ParseError {
+                    kind: ParseErrorKind::NonAssociative(
+                        String::from_utf8_lossy(&self.raw[token.span.start..token.span.end])
+                            .into_owned(),
+                    ),
+                    span: token.span,
+                });
+            }
+        }
+        Ok(left)
+    }
+
+    fn binary(
+        &mut self,
+        left: Expr,
+        precedence: Precedence,
+        associativity: Associativity,
+    ) -> Result<Expr, ParseError> {
+        let token = self.advance();
+        let op = binary_op(&token.token_type).expect("infix token without a binary operator");
+        // right associative operators take the same level again on their right.
+        let right = match associativity {
+            Associativity::Right => self.expression_above(precedence.below())?,
+            _ => self.expression_above(precedence)?,
+        };
+        Ok(Expr {
+            span: left.span.to(right.span),
+            kind: ExprKind::Binary {
+                op,
+                left: Box::new(left),
+                right: Box::new(right),
+            },
+        })
+    }
+
+    // assignment is right associative: a = b = c is a = (b = c).
+    fn assignment(&mut self, target: Expr) -> Result<Expr, ParseError> {
+        let op = match self.peek().token_type {
+            TokenType::PlusAssign => AssignOp::Add,
+            TokenType::MinusAssign => AssignOp::Sub,
+            TokenType::AsteriskAssign => AssignOp::Mul,
+            TokenType::SlashAssign => AssignOp::Div,
+            TokenType::PercentAssign => AssignOp::Rem,
+            TokenType::DoubleAsteriskAssign => AssignOp::Pow,
+            TokenType::DoubleSlashAssign => AssignOp::IntDiv,
+            _ => AssignOp::Assign,
+        };
+        if !matches!(
+            target.kind,
+            ExprKind::Identifier(_) | ExprKind::Index { .. } | ExprKind::Member { .. }
+        ) {
+            return Err(ParseError {
+                kind: ParseErrorKind::InvalidAssignmentTarget,
+                span: target.span,
+            });
+        }
+        self.advance();
+        let value = self.expression_above(Precedence::Assignment.below())?;
+        Ok(Expr {
+            span: target.span.to(value.span),
+            kind: ExprKind::Assign {
+                op,
+                target: Box::new(target),
+                value: Box::new(value),
+            },
+        })
+    }
+
+    // prefix operators bind looser than ** and the postfix operators,
+    // so -a ** 2 is -(a ** 2) and -f(x) is -(f(x)).
+    fn prefix(&mut self) -> Result<Expr, ParseError> {
+        let op = match self.peek().token_type {
+            TokenType::Minus => UnaryOp::Neg,
+            TokenType::Bang | TokenType::Not => UnaryOp::Not,
+            TokenType::Tilde => UnaryOp::BitNot,
+            _ => return self.primary(),
+        };
+        let start = self.advance().span;
+        let operand = self.expression_above(Precedence::Prefix)?;
+        Ok(Expr {
+            span: start.to(operand.span),
+            kind: ExprKind::Unary {
+                op,
+                operand: Box::new(operand),
+            },
+        })
+    }
+
+    // call, index, member access and `?` after `left`.
+    fn postfix(&mut self, left: Expr) -> Result<Expr, ParseError> {
+        let start = left.span;
+        let token = self.advance();
+        let kind = match token.token_type {
+            TokenType::LeftParen => {
+                let mut args = vec![];
+                while !self.at(&TokenType::RightParen) {
+                    args.push(self.expression()?);
+                    if !self.eat(&TokenType::Comma) {
+                        break;
+                    }
+                }
+                self.expect(&TokenType::RightParen, "')'")?;
+                ExprKind::Call {
+                    callee: Box::new(left),
+                    args,
+                }
+            }
+            TokenType::LeftSquare => {
+                let index = self.expression()?;
+                self.expect(&TokenType::RightSquare, "']'")?;
+                ExprKind::Index {
+                    object: Box::new(left),
+                    index: Box::new(index),
+                }
+            }
+            TokenType::Dot => {
+                let name = self.ident("a field name")?;
+                ExprKind::Member {
+                    object: Box::new(left),
+                    name,
+                }
+            }
+            // TokenType::Question
+            _ => ExprKind::Unary {
+                op: UnaryOp::Try,
+                operand: Box::new(left),
+            },
+        };
+        Ok(Expr {
+            span: start.to(self.previous),
+            kind,
+        })
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        let token = self.peek().clone();
+        let kind = match token.token_type {
+            TokenType::Integer(value) => ExprKind::Integer(value),
+            TokenType::Float(value) => ExprKind::Float(value),
+            TokenType::StringLiteral(value) => ExprKind::String(value),
+            TokenType::True => ExprKind::Bool(true),
+            TokenType::False => ExprKind::Bool(false),
+            TokenType::Nil => ExprKind::Nil,
+            // the lexer already reported it.
+            TokenType::Error => ExprKind::Error,
+            TokenType::Identifier(name) => {
+                ExprKind::Identifier(String::from_utf8_lossy(&name).into_owned())
+            }
+            TokenType::LeftParen => {
+                self.advance();
+                let mut inner = self.expression()?;
+                let end = self.expect(&TokenType::RightParen, "')'")?.span;
+                // the parentheses are part of the expression's source.
+                inner.span = token.span.to(end);
+                return Ok(inner);
+            }
+            _ => return Err(self.unexpected("an expression")),
+        };
+        self.advance();
+        Ok(Expr {
+            kind,
+            span: token.span,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(source: &str) -> String {
+        let file = File::new(source.as_bytes().to_vec());
+        match parse(&file) {
+            Ok(program) => program.to_string(),
+            Err(e) => panic!("{source:?}: {e:?}"),
+        }
+    }
+
+    // the first error in `source`.
+    fn parse_error(source: &str) -> ParseError {
+        errors(source).remove(0)
+    }
+
+    fn errors(source: &str) -> Vec<ParseError> {
+        let file = File::new(source.as_bytes().to_vec());
+        parse(&file).unwrap_err()
+    }
+
+    // the tree of a source with errors.
+    fn recovered(source: &str) -> String {
+        let file = File::new(source.as_bytes().to_vec());
+        parse_with_recovery(&file).0.to_string()
+    }
+
+    #[test]
+    fn binary_precedence() {
+        assert_eq!(parsed("1 + 2 * 3"), "(+ 1 (* 2 3))");
+        assert_eq!(parsed("1 - 2 - 3"), "(- (- 1 2) 3)");
+        assert_eq!(parsed("(1 + 2) * 3"), "(* (+ 1 2) 3)");
+        assert_eq!(parsed("a < b == c >= d"), "(== (< a b) (>= c d))");
+        assert_eq!(parsed("a or b and not c"), "(or a (and b (not c)))");
+        assert_eq!(parsed("a || b && !c"), "(or a (and b (not c)))");
+        assert_eq!(parsed("-a % 2 // 3"), "(// (% (- a) 2) 3)");
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(parsed("1.0"), "1.0");
+        assert_eq!(parsed("\"hi\\n\""), "\"hi\\n\"");
+        assert_eq!(parsed("true; false; nil"), "true\nfalse\nnil");
+    }
+
+    #[test]
+    fn assignment() {
+        assert_eq!(parsed("a = b = 1"), "(= a (= b 1))");
+        assert_eq!(parsed("a += 1\nb -= 2\nc **= 3"), "(+= a 1)\n(-= b 2)\n(**= c 3)");
+        assert_eq!(
+            parse_error("1 = a").kind,
+            ParseErrorKind::InvalidAssignmentTarget
+        );
+    }
+
+    #[test]
+    fn calls() {
+        assert_eq!(parsed("print()"), "(call print)");
+        assert_eq!(parsed("f(a, 1 + 2,)(3)"), "(call (call f a (+ 1 2)) 3)");
+        // newlines inside parentheses don't end the statement.
+        assert_eq!(parsed("f(a,\n  b)"), "(call f a b)");
+    }
+
+    #[test]
+    fn documented_groupings() {
+        assert_eq!(parsed("a == 1 & b < 2"), "(== a (< (& 1 b) 2))");
+        assert_eq!(parsed("-2 ** 2"), "(- (** 2 2))");
+        assert_eq!(parsed("2 ** -1"), "(** 2 (- 1))");
+        assert_eq!(parsed("not a == b"), "(== (not a) b)");
+        assert_eq!(parsed("a | b ^ c & d << 1 + 2"), "(| a (^ b (& c (<< d (+ 1 2)))))");
+        assert_eq!(parsed("x = 0..n + 1"), "(= x (.. 0 (+ n 1)))");
+        assert_eq!(parsed("a or b..=c"), "(..= (or a b) c)");
+    }
+
+    #[test]
+    fn right_associativity() {
+        assert_eq!(parsed("2 ** 3 ** 2"), "(** 2 (** 3 2))");
+        assert_eq!(parsed("a = b += c"), "(= a (+= b c))");
+        assert_eq!(parsed("a ** b * c"), "(* (** a b) c)");
+    }
+
+    #[test]
+    fn prefix_and_postfix() {
+        assert_eq!(parsed("~-!a"), "(~ (- (not a)))");
+        assert_eq!(parsed("a.b[c](d)?"), "(? (call (index (. a b) c) d))");
+        assert_eq!(parsed("-f(x)?"), "(- (? (call f x)))");
+        assert_eq!(parsed("a[i] = x.y = 1"), "(= (index a i) (= (. x y) 1))");
+        assert_eq!(parsed("a[\n  1\n]"), "(index a 1)");
+    }
+
+    #[test]
+    fn operator_errors() {
+        assert_eq!(
+            parse_error("1..2..3").kind,
+            ParseErrorKind::NonAssociative("..".to_string())
+        );
+        assert_eq!(
+            parse_error("f() = 1").kind,
+            ParseErrorKind::InvalidAssignmentTarget
+        );
+        assert_eq!(
+            parse_error("a + b = 1").kind,
+            ParseErrorKind::InvalidAssignmentTarget
+        );
+        assert_eq!(
+            parse_error("a.(b)").kind,
+            ParseErrorKind::Expected {
+                expected: "a field name",
+                found: "'('".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn if_else() {
+        assert_eq!(parsed("if a == 1 :\n  print(a)\n"), "(if (== a 1) (block (call print a)))");
+        assert_eq!(
+            parsed("if a:\n  b\nelse if c:\n  d\nelse:\n  e\n  f"),
+            "(if a (block b) (block (if c (block d) (block e f))))"
+        );
+    }
+
+    #[test]
+    fn loops_functions_and_return() {
+        assert_eq!(parsed("while x < 3:\n  x += 1"), "(while (< x 3) (block (+= x 1)))");
+        assert_eq!(parsed("for i in range(3):\n  print(i)"), "(for i (call range 3) (block (call print i)))");
+        assert_eq!(
+            parsed("function add(a, b):\n  return a + b\nfunction f():\n  return"),
+            "(function add (a b) (block (return (+ a b))))\n(function f () (block (return)))"
+        );
+    }
+
+    #[test]
+    fn statement_separators() {
+        assert_eq!(parsed("a = 1; b = 2\nc = 3;\n"), "(= a 1)\n(= b 2)\n(= c 3)");
+        let error = parse_error("a = hello world;");
+        assert_eq!(
+            error.kind,
+            ParseErrorKind::Expected {
+                expected: "end of statement",
+                found: "'world'".to_string()
+            }
+        );
+        assert_eq!((error.span.line, error.span.col), (1, 11));
+    }
+
+    #[test]
+    fn spans() {
+        let file = File::new(b"x = f(1, 2)\nif x:\n  y\n".to_vec());
+        let program = parse(&file).unwrap();
+        let assign = &program.statements[0];
+        assert_eq!((assign.span.start, assign.span.end), (0, 11));
+        let StmtKind::Expr(Expr { kind: ExprKind::Assign { value, .. }, .. }) = &assign.kind else {
+            panic!("not an assignment: {assign}");
+        };
+        assert_eq!((value.span.start, value.span.end), (4, 11));
+
+        // the if ends with its block, not at the next line.
+        let if_statement = &program.statements[1];
+        assert_eq!((if_statement.span.start, if_statement.span.end), (12, 21));
+        assert_eq!((if_statement.span.line, if_statement.span.col), (2, 1));
+    }
+
+    #[test]
+    fn first_errors() {
+        assert_eq!(
+            parse_error("if a\n  b").kind,
+            ParseErrorKind::Expected {
+                expected: "'{' or ':'",
+                found: "end of line".to_string()
+            }
+        );
+        assert_eq!(
+            parse_error("f(1").kind,
+            ParseErrorKind::Expected {
+                expected: "')'",
+                found: "end of line".to_string()
+            }
+        );
+        // the lexer's error is reported when it comes first.
+        assert_eq!(
+            parse_error("a = \"open").kind,
+            ParseErrorKind::Lex(LexErrorKind::UnterminatedString)
+        );
+        assert_eq!(
+            parse_error("a b $").kind,
+            ParseErrorKind::Expected {
+                expected: "end of statement",
+                found: "'b'".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn every_error_is_reported() {
+        let errors = errors("a = = 1\nb = 2 3\nc = * 3\nd = 4");
+        let lines: Vec<usize> = errors.iter().map(|e| e.span.line).collect();
+        assert_eq!(lines, vec![1, 2, 3]);
+        assert_eq!(recovered("a = = 1\nb = 2 3\nc = * 3\nd = 4"), "(error)\n(error)\n(error)\n(= d 4)");
+    }
+
+    #[test]
+    fn synchronizes_on_semicolons_and_keywords() {
+        assert_eq!(recovered("a b; c = 1"), "(error)\n(= c 1)");
+        assert_eq!(recovered("a b while x:\n  y"), "(error)\n(while x (block y))");
+        assert_eq!(recovered(")\nx"), "(error)\nx");
+    }
+
+    #[test]
+    fn errors_inside_blocks() {
+        let source = "if a:\n  b = = 1\n  c\nelse:\n  d 1\ne";
+        assert_eq!(errors(source).len(), 2);
+        assert_eq!(recovered(source), "(if a (block (error) c) (block (error)))\ne");
+    }
+
+    #[test]
+    fn bad_headers_keep_their_body() {
+        assert_eq!(recovered("if a ==:\n  b"), "(if (error) (block b))");
+        // a header that can't be saved skips its body, but still checks it.
+        let source = "function (a):\n  b = = 1\nc";
+        assert_eq!(recovered(source), "(error)\nc");
+        assert_eq!(errors(source).len(), 2);
+    }
+
+    #[test]
+    fn unexpected_indentation() {
+        let source = "a\n  b\nc";
+        assert_eq!(recovered(source), "a\nb\nc");
+        assert_eq!(
+            parse_error(source).kind,
+            ParseErrorKind::Expected {
+                expected: "a statement",
+                found: "indentation".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn lexer_errors_become_error_nodes() {
+        let errors = errors("a = $ + 1\nb = \"x");
+        assert_eq!(
+            errors.iter().map(|e| e.kind.clone()).collect::<Vec<_>>(),
+            vec![
+                ParseErrorKind::Lex(LexErrorKind::UnknownCharacter('$')),
+                ParseErrorKind::Lex(LexErrorKind::UnterminatedString),
+            ]
+        );
+        assert_eq!(recovered("a = $ + 1\nb = \"x"), "(= a (+ (error) 1))\n(= b (error))");
+    }
+
+    #[test]
+    fn brace_blocks() {
+        assert_eq!(parsed("if a { b }"), "(if a (block b))");
+        assert_eq!(parsed("if a {}\nc"), "(if a (block))\nc");
+        assert_eq!(
+            parsed("if a {\n  b\n} else if c {\n  d; e\n} else {\n  f\n}\ng"),
+            "(if a (block b) (block (if c (block d e) (block f))))\ng"
+        );
+        assert_eq!(
+            parsed("function f(a) {\n    return a\n    }\nwhile x { for i in y { z } }"),
+            "(function f (a) (block (return a)))\n(while x (block (for i y (block z))))"
+        );
+    }
+
+    #[test]
+    fn both_block_styles_give_the_same_tree() {
+        let braces = "if a == 1 {\n  print(a)\n} else {\n  print(b)\n}\nc";
+        let indent = "if a == 1:\n  print(a)\nelse:\n  print(b)\nc";
+        assert_eq!(parsed(braces), parsed(indent));
+        // and they mix.
+        assert_eq!(
+            parsed("while a {\n  if b:\n    c\n  d\n}"),
+            "(while a (block (if b (block c)) d))"
+        );
+    }
+
+    #[test]
+    fn block_style_pragma() {
+        let source = "# header\n# lang: blocks = braces\n\nif a { b }\nwhile c:\n  d";
+        let error = parse_error(source);
+        assert_eq!(error.kind, ParseErrorKind::WrongBlockStyle(BlockStyle::Braces));
+        assert_eq!((error.span.line, error.span.col), (5, 8));
+        // the block is still there.
+        assert_eq!(recovered(source), "(if a (block b))\n(while c (block d))");
+
+        let error = parse_error("#lang: blocks=indent\nif a { b }");
+        assert_eq!(error.kind, ParseErrorKind::WrongBlockStyle(BlockStyle::Indent));
+        assert_eq!(parsed("#lang: blocks=indent\nif a:\n  b"), "(if a (block b))");
+
+        // only comments before the code count.
+        assert_eq!(parsed("if a { b }\n# lang: blocks = indent"), "(if a (block b))");
+
+        let error = parse_error("# lang: blocks = tabs\nx");
+        assert_eq!(error.kind, ParseErrorKind::InvalidPragma("blocks = tabs".to_string()));
+        assert_eq!(error.span.line, 1);
+    }
+
+    #[test]
+    fn brace_block_errors() {
+        assert_eq!(recovered("if a { b = = 1 }\nc"), "(if a (block (error)))\nc");
+        assert_eq!(
+            parse_error("if a {\n  b\n").kind,
+            ParseErrorKind::Expected {
+                expected: "'}'",
+                found: "end of file".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn newlines_end_statements() {
+        assert_eq!(parsed("a = 1\nb = 2"), "(= a 1)\n(= b 2)");
+        assert_eq!(parsed("a = 1;\nb = 2;"), "(= a 1)\n(= b 2)");
+        assert_eq!(parsed(";a = 1;; b = 2;\n;"), "(= a 1)\n(= b 2)");
+        assert_eq!(parsed("if a { ; b; }"), "(if a (block b))");
+    }
+
+    #[test]
+    fn lines_that_go_on() {
+        assert_eq!(parsed("total = price *\n    count\nx"), "(= total (* price count))\nx");
+        assert_eq!(parsed("f(a,\nb)\ng(a,\n  b)"), "(call f a b)\n(call g a b)");
+        assert_eq!(parsed("if a and\n  b:\n  c"), "(if (and a b) (block c))");
+        assert_eq!(parsed("x = a.\n  b"), "(= x (. a b))");
+        // a line starting with an operator is a new statement.
+        assert_eq!(parsed("a\n-b"), "a\n(- b)");
+    }
+}
@@ -0,0 +1,145 @@
+/// A half-open byte range `start..end` into the source, together with the
+/// 1 based line and column of `start`. Columns are counted in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, col: usize) -> Self {
+        Self { start, end, line, col }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(&self, other: Span) -> Span {
+        if other.start < self.start {
+            return other.to(*self);
+        }
+        Span::new(self.start, self.end.max(other.end), self.line, self.col)
+    }
+}
+
+/// 1 based column of the end of `line`, where `line` is everything from the
+/// start of the line up to the position. utf-8 continuation bytes are skipped
+/// so multi byte characters count once.
+pub(crate) fn column(line: &[u8]) -> usize {
+    line.iter().filter(|b| (**b & 0xC0) != 0x80).count() + 1
+}
+
+/// Converts byte offsets into line/column positions.
+#[derive(Debug)]
+pub struct SourceMap<'a> {
+    source: &'a [u8],
+    // byte offset where each line starts. line_starts[0] is always 0.
+    line_starts: Vec<usize>,
+}
+
+impl<'a> SourceMap<'a> {
+    pub fn new(source: &'a [u8]) -> Self {
+        let mut line_starts = vec![0];
+        for (i, b) in source.iter().enumerate() {
+            if *b == b'\n' {
+                line_starts.push(i + 1);
+            }
+        }
+        Self { source, line_starts }
+    }
+
+    /// 1 based line and column of `offset`. offsets past the end of the source
+    /// are clamped to it.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.source.len());
+        // the last line starting at or before the offset.
+        let line = self.line_starts.partition_point(|start| *start <= offset) - 1;
+        let col = column(&self.source[self.line_starts[line]..offset]);
+        (line + 1, col)
+    }
+
+    /// Span for the byte range `start..end`.
+    pub fn span(&self, start: usize, end: usize) -> Span {
+        let (line, col) = self.line_col(start);
+        Span::new(start, end, line, col)
+    }
+
+    /// The text of line `line` (1 based) without its line ending.
+    pub fn line_text(&self, line: usize) -> Option<&'a [u8]> {
+        let start = *self.line_starts.get(line.checked_sub(1)?)?;
+        let end = self
+            .line_starts
+            .get(line)
+            .map(|next| next - 1)
+            .unwrap_or(self.source.len());
+        let text = &self.source[start..end];
+        Some(text.strip_suffix(b"\r").unwrap_or(text))
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_col() {
+        let map = SourceMap::new(b"ab\ncd\n");
+        assert_eq!(map.line_col(0), (1, 1));
+        assert_eq!(map.line_col(2), (1, 3)); // the \n ends line 1
+        assert_eq!(map.line_col(3), (2, 1));
+        assert_eq!(map.line_col(6), (3, 1)); // end of file, after the last \n
+        assert_eq!(map.line_col(100), (3, 1));
+        assert_eq!(map.span(3, 5), Span::new(3, 5, 2, 1));
+    }
+
+    #[test]
+    fn columns_count_characters() {
+        // π is two bytes but one column.
+        let map = SourceMap::new("π = 3\n".as_bytes());
+        assert_eq!(map.line_col(2), (1, 2));
+        assert_eq!(map.line_col(5), (1, 5));
+        assert_eq!(column("ππ".as_bytes()), 3);
+    }
+
+    #[test]
+    fn line_text() {
+        let map = SourceMap::new(b"one\r\ntwo\nthree");
+        assert_eq!(map.line_count(), 3);
+        assert_eq!(map.line_text(0), None);
+        assert_eq!(map.line_text(1), Some(&b"one"[..]));
+        assert_eq!(map.line_text(2), Some(&b"two"[..]));
+        assert_eq!(map.line_text(3), Some(&b"three"[..]));
+        assert_eq!(map.line_text(4), None);
+
+        // a trailing newline starts an empty last line.
+        let map = SourceMap::new(b"a\n");
+        assert_eq!(map.line_count(), 2);
+        assert_eq!(map.line_text(2), Some(&b""[..]));
+        assert_eq!(SourceMap::new(b"").line_count(), 1);
+    }
+
+    #[test]
+    fn span_to() {
+        let a = Span::new(2, 4, 1, 3);
+        let b = Span::new(6, 9, 2, 1);
+        assert_eq!(a.to(b), Span::new(2, 9, 1, 3));
+        // the result starts at whichever span comes first.
+        assert_eq!(b.to(a), Span::new(2, 9, 1, 3));
+        // a span inside another one doesn't extend it.
+        assert_eq!(Span::new(0, 10, 1, 1).to(a), Span::new(0, 10, 1, 1));
+        assert_eq!(a.len(), 2);
+        assert!(Span::new(5, 5, 1, 6).is_empty());
+    }
+}